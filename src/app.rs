@@ -1,24 +1,50 @@
 use anyhow::Result;
+use chrono::{Local, Utc};
 use eframe::egui::{
-    self, Align, Button, CentralPanel, Context, Grid, Layout, MenuBar, Pos2, RichText,
-    TopBottomPanel, Window,
+    self, Align, Button, CentralPanel, Color32, Context, Grid, Layout, MenuBar, Pos2, RichText,
+    TextEdit, TopBottomPanel, Window,
+};
+use std::{
+    cell::RefCell,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::Duration,
 };
-use std::{cell::RefCell, rc::Rc};
 use tracing::{error, info};
 
-use crate::{Compiler, Connection, DialogPurpose, FilePicker, Workspace};
+use crate::{
+    compiler::{Compilation, Debugger},
+    libraries::LibraryPaths,
+    palette::{first_compatible_input, Palette},
+    session::{Session, Snapshot, AUTOSAVE_INTERVAL},
+    watch::Watch,
+    workspace::WorkspaceFormat,
+    Compiler, Connection, DialogPurpose, FilePicker, Workspace,
+};
 
 pub struct Shared {
     pub cursor: Pos2,
-    pub add_menu: Option<(Pos2, Option<String>)>,
     pub error: Option<String>,
     pub compile_debug_info: bool,
+    pub compile_optimize: bool,
+    pub library_dirs: Vec<PathBuf>, // Searched for libraries, which workspaces then refer to without a path
 }
 
 pub struct App {
     workspace: Option<Workspace>,
     shared: Rc<RefCell<Shared>>,
     picker: Option<FilePicker>,
+    libraries: LibraryPaths,
+    palette: Palette,
+    link_from: Option<(usize, usize)>, // Output to connect the node picked in the palette to
+    show_library_paths: bool,
+    new_library_dir: String,
+    watch: Watch,
+    debugger: Option<Debugger>,
+    session: Session,
+    saved_text: Option<String>, // The workspace as last opened or saved, to tell unsaved changes
+    recovery: Option<Snapshot>, // Unsaved changes from the last run, until recovered or discarded
 }
 
 impl App {
@@ -27,121 +53,403 @@ impl App {
             workspace: None,
             shared: Rc::new(RefCell::new(Shared {
                 cursor: Pos2::ZERO,
-                add_menu: None,
                 error: None,
                 compile_debug_info: false,
+                compile_optimize: false,
+                library_dirs: Vec::new(),
             })),
             picker: None,
+            libraries: LibraryPaths::new(),
+            palette: Palette::new(),
+            link_from: None,
+            show_library_paths: false,
+            new_library_dir: String::new(),
+            watch: Watch::new(),
+            debugger: None,
+            session: Session::default(),
+            saved_text: None,
+            recovery: None,
         })
     }
 
+    /// Restores the recent files from `storage` and reopens the last
+    /// workspace. Unsaved changes from the last run are offered for recovery.
+    pub fn restore_session(&mut self, storage: Option<&dyn eframe::Storage>) {
+        let mut session = Session::load(storage);
+        self.recovery = session.snapshot.take();
+        self.palette
+            .set_recent(std::mem::take(&mut session.recent_nodes));
+        let path = session.path.take();
+        self.session = session;
+        if let Some(path) = path
+            && path.exists()
+        {
+            self.open_workspace(path);
+        }
+    }
+
     fn new_workspace(&mut self) -> Result<()> {
-        let workspace = Workspace::new(self.shared.clone());
+        let mut workspace = Workspace::new(self.shared.clone());
+        if let Err(e) = workspace.import_std_libs() {
+            error!("Failed to import std libs: {e:?}");
+            self.shared.borrow_mut().error = Some(format!("Failed to import std libs: {e:?}"));
+        }
         self.workspace = Some(workspace);
+        self.load_search_path_libs();
+        self.session.path = None;
+        self.saved_text = self.workspace_text();
         Ok(())
     }
 
-    fn set_style(ctx: &Context) {
-        let mut visuals = egui::Visuals::dark();
-        visuals.override_text_color = Some(egui::Color32::WHITE);
-        ctx.set_visuals(visuals);
-
-        let mut style = (*ctx.style()).clone();
-        style.text_styles.insert(
-            egui::TextStyle::Heading,
-            egui::FontId::new(12.0, egui::FontFamily::Proportional),
-        );
-        ctx.set_style(style);
+    fn open_workspace(&mut self, path: PathBuf) {
+        info!(?path, "Opening workspace");
+        let search_libs = self.scan_libraries();
+        match Workspace::load(self.shared.clone(), path.clone(), search_libs.clone()) {
+            Ok(workspace) => {
+                self.workspace = Some(workspace);
+                self.record_search_libs(&search_libs);
+                self.session.path = Some(path.clone());
+                self.saved_text = self.workspace_text();
+                self.session.add_recent(path);
+            }
+            Err(e) => {
+                error!("Failed to open workspace: {e:?}");
+                self.shared.borrow_mut().error = Some(format!("Failed to open workspace: {e:?}"));
+                if !path.exists() {
+                    self.session.recent.retain(|p| p != &path);
+                }
+            }
+        }
     }
 
-    // self.workspace.unwrap() is safe since this is only called after the check int he start of the function.
-    fn render_add_node(&mut self, ctx: &Context) {
-        if self.shared.borrow().add_menu.is_none() {
+    fn save_workspace(&mut self, path: PathBuf) {
+        info!(?path, "Saving workspace");
+        let Some(workspace) = &self.workspace else {
+            error!("No workspace to save.");
+            self.shared.borrow_mut().error = Some("No workspace to save.".to_string());
+            return;
+        };
+        if let Err(e) = workspace.save(path.clone()) {
+            error!("Failed to save workspace: {e:?}");
+            self.shared.borrow_mut().error = Some(format!("Failed to save workspace: {e:?}"));
             return;
         }
+        self.session.path = Some(path.clone());
+        self.saved_text = self.workspace_text();
+        self.session.add_recent(path);
+    }
+
+    /// Saves to the file the workspace came from, or asks for one.
+    fn save_or_pick(&mut self) {
         if self.workspace.is_none() {
-            self.shared.borrow_mut().error = Some(
-                "App::render_add_node has been called with workspace == None... WHY?!".to_string(),
-            );
             return;
         }
+        match self.session.path.clone() {
+            Some(path) => self.save_workspace(path),
+            None => self.picker = Some(FilePicker::new(DialogPurpose::SaveWorkspace)),
+        }
+    }
 
-        let add_menu = self.shared.borrow().add_menu.clone().unwrap();
-        let mut reset_menu = false;
-        let mut add: Option<(String, String)> = None;
+    /// The open workspace as RON, as it would be saved next to its file.
+    fn workspace_text(&self) -> Option<String> {
+        let dir = self
+            .session
+            .path
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new("."));
+        match self.workspace.as_ref()?.to_text(WorkspaceFormat::Ron, dir) {
+            Ok(text) => Some(text),
+            Err(e) => {
+                error!("Failed to serialize workspace: {e:?}");
+                None
+            }
+        }
+    }
 
-        Window::new(format!(
-            "Add {}",
-            add_menu.clone().1.unwrap_or("Node".to_string())
-        ))
-        .fixed_pos(add_menu.0)
-        .collapsible(false)
-        .title_bar(true)
-        .movable(true)
-        .resizable(false)
-        .frame(egui::Frame {
-            inner_margin: 4.0.into(),
-            corner_radius: 0.into(),
-            fill: egui::Color32::from_hex("#202020").unwrap(),
-            stroke: egui::Stroke::new(1.0, egui::Color32::from_gray(100)),
-            shadow: egui::epaint::Shadow::NONE,
-            ..Default::default()
+    /// The open workspace, if it has unsaved changes.
+    fn snapshot(&self) -> Option<Snapshot> {
+        let text = self.workspace_text()?;
+        if self.saved_text.as_ref() == Some(&text) {
+            return None;
+        }
+        Some(Snapshot {
+            path: self.session.path.clone(),
+            timestamp: Utc::now(),
+            text,
         })
-        .show(ctx, |ui| {
-            if let Some(category) = add_menu.clone().1 {
-                if ui.button("Close").clicked() {
-                    self.shared.borrow_mut().add_menu = None;
-                }
-                if ui.button("Back").clicked() {
-                    reset_menu = true;
+    }
+
+    fn recover(&mut self, snapshot: Snapshot) {
+        info!(path = ?snapshot.path, "Recovering workspace");
+        let path = snapshot.path.clone().unwrap_or_default();
+        let search_libs = self.scan_libraries();
+        match Workspace::from_text(
+            self.shared.clone(),
+            &snapshot.text,
+            WorkspaceFormat::Ron,
+            &path,
+            search_libs.clone(),
+        ) {
+            Ok(workspace) => {
+                self.workspace = Some(workspace);
+                self.record_search_libs(&search_libs);
+                self.session.path = snapshot.path;
+                self.saved_text = None;
+            }
+            Err(e) => {
+                error!("Failed to recover workspace: {e:?}");
+                self.shared.borrow_mut().error =
+                    Some(format!("Failed to recover workspace: {e:?}"));
+            }
+        }
+    }
+
+    fn render_recovery(&mut self, ctx: &Context) {
+        let Some(snapshot) = &self.recovery else {
+            return;
+        };
+        let mut recover = false;
+        let mut discard = false;
+        Window::new("Recover Workspace")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let name = snapshot.path.as_ref().map_or_else(
+                    || "an untitled workspace".to_string(),
+                    |p| p.display().to_string(),
+                );
+                ui.label(format!(
+                    "The last session ended with unsaved changes to {}, kept at {}.",
+                    name,
+                    snapshot
+                        .timestamp
+                        .with_timezone(&Local)
+                        .format("%Y-%m-%d %H:%M")
+                ));
+                ui.horizontal(|ui| {
+                    recover = ui.button("Recover").clicked();
+                    discard = ui.button("Discard").clicked();
+                });
+            });
+        if recover && let Some(snapshot) = self.recovery.take() {
+            self.recover(snapshot);
+        } else if discard {
+            self.recovery = None;
+        }
+    }
+
+    /// Imports every library found in the library search paths into the current workspace.
+    fn load_search_path_libs(&mut self) {
+        let paths = self.scan_libraries();
+        self.import_libs(paths);
+    }
+
+    /// Every library in the search directories.
+    fn scan_libraries(&mut self) -> Vec<PathBuf> {
+        self.shared.borrow_mut().library_dirs = self.libraries.dirs();
+        self.libraries.scan()
+    }
+
+    fn import_libs(&mut self, paths: Vec<PathBuf>) {
+        if paths.is_empty() {
+            return;
+        }
+        info!(count = paths.len(), "Importing libs");
+        if let Some(workspace) = self.workspace.as_mut() {
+            let failed = workspace.import_libs(paths.clone());
+            self.libraries.record(&paths, &failed);
+        } else {
+            self.shared.borrow_mut().error = Some("No workspace to import lib into.".to_string());
+        }
+    }
+
+    /// Records which of `search_libs` the workspace just loaded with.
+    fn record_search_libs(&mut self, search_libs: &[PathBuf]) {
+        let Some(workspace) = &self.workspace else {
+            return;
+        };
+        let storage = &workspace.data.desc_storage;
+        let failed: Vec<PathBuf> = search_libs
+            .iter()
+            .filter(|path| {
+                !storage
+                    .libs
+                    .iter()
+                    .any(|lib| lib.source.as_ref() == Some(*path))
+            })
+            .cloned()
+            .collect();
+        self.libraries.record(search_libs, &failed);
+    }
+
+    fn render_error(&mut self, ctx: &Context) {
+        let Some(error) = self.shared.borrow().error.clone() else {
+            return;
+        };
+        let mut open = true;
+        let mut dismissed = false;
+        Window::new("Error")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.colored_label(Color32::LIGHT_RED, error);
+                if ui.button("Dismiss").clicked() {
+                    dismissed = true;
                 }
-                ui.add_space(8.0);
+            });
+        if !open || dismissed {
+            self.shared.borrow_mut().error = None;
+        }
+    }
 
-                if let Some(descs) = self
-                    .workspace
-                    .as_ref()
-                    .unwrap()
-                    .data
-                    .desc_storage
-                    .descs_category(&category)
-                {
-                    for desc in descs {
-                        if ui.button(&desc.title).clicked() {
-                            add = Some((category.clone(), desc.title.clone()));
-                            reset_menu = true;
+    fn render_library_paths(&mut self, ctx: &Context) {
+        if !self.show_library_paths {
+            return;
+        }
+
+        let mut open = true;
+        let mut remove: Option<PathBuf> = None;
+        let mut add = false;
+        let mut rescan = false;
+        Window::new("Library Paths")
+            .open(&mut open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                Grid::new("library_paths").striped(true).show(ui, |ui| {
+                    for dir in self.libraries.dirs() {
+                        ui.label(dir.display().to_string());
+                        if !dir.exists() {
+                            ui.weak("missing");
+                        } else {
+                            ui.label("");
+                        }
+                        if self.libraries.extra_dirs.contains(&dir) && ui.button("Remove").clicked()
+                        {
+                            remove = Some(dir.clone());
                         }
+                        ui.end_row();
                     }
-                }
-            } else {
-                if ui.button("Close").clicked() {
-                    self.shared.borrow_mut().add_menu = None;
-                }
-                ui.add_space(8.0);
-
-                for category in &self
-                    .workspace
-                    .as_ref()
-                    .unwrap()
-                    .data
-                    .desc_storage
-                    .categories()
-                {
-                    if ui.button(category).clicked() {
-                        self.shared.borrow_mut().add_menu =
-                            Some((add_menu.clone().0, Some(category.clone())));
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.add(TextEdit::singleline(&mut self.new_library_dir).hint_text("Directory"));
+                    if ui.button("Add").clicked() && !self.new_library_dir.is_empty() {
+                        add = true;
                     }
+                });
+                if ui.button("Rescan").clicked() {
+                    rescan = true;
                 }
+            });
+
+        if let Some(dir) = &remove {
+            self.libraries.extra_dirs.retain(|d| d != dir);
+        }
+        if add {
+            let dir = PathBuf::from(std::mem::take(&mut self.new_library_dir));
+            self.libraries.extra_dirs.push(dir);
+        }
+        if add || remove.is_some() {
+            if let Err(e) = self.libraries.save_config() {
+                error!("Failed to save library paths: {e:?}");
+                self.shared.borrow_mut().error =
+                    Some(format!("Failed to save library paths: {e:?}"));
             }
-        });
+            rescan = true;
+        }
+        if rescan {
+            self.load_search_path_libs();
+        }
+        self.show_library_paths = open;
+    }
+
+    fn set_style(ctx: &Context) {
+        let mut visuals = egui::Visuals::dark();
+        visuals.override_text_color = Some(egui::Color32::WHITE);
+        ctx.set_visuals(visuals);
+
+        let mut style = (*ctx.style()).clone();
+        style.text_styles.insert(
+            egui::TextStyle::Heading,
+            egui::FontId::new(12.0, egui::FontFamily::Proportional),
+        );
+        ctx.set_style(style);
+    }
 
-        if reset_menu {
-            self.shared.borrow_mut().add_menu = Some((add_menu.0, None));
+    fn render_add_node(&mut self, ctx: &Context) {
+        let Some(workspace) = self.workspace.as_mut() else {
+            self.palette.close();
+            return;
+        };
+        let Some(pos) = self.palette.pos else {
+            self.link_from = None;
+            return;
+        };
+
+        if let Some((category, title)) = self.palette.show(ctx, &workspace.data.desc_storage)
+            && let Some(node_id) = workspace.add_node(category, title, pos)
+            && let Some(from) = self.link_from.take()
+            && let Some(output_type) = workspace.output_type(from.0, from.1)
+            && let Some(input) =
+                first_compatible_input(&workspace.data.nodes[node_id].desc, &output_type)
+        {
+            workspace.data.connections.push(Connection {
+                from,
+                to: (node_id, input),
+            });
+            workspace.verify_connections();
         }
+    }
 
-        if let Some((category, title)) = add {
-            self.workspace.as_mut().unwrap().add_node(category, title);
-            self.shared.borrow_mut().add_menu = None;
+    /// A compiler for the open workspace. The error is ready to be shown to
+    /// the user.
+    fn compiler(&self) -> Result<Compiler, String> {
+        let workspace = self.workspace.as_ref().unwrap();
+        let invalid = workspace.invalid_fields();
+        if !invalid.is_empty() {
+            let list = invalid
+                .iter()
+                .map(|(node, field, error)| format!("{node} › {field}: {error}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            error!("Compilation refused, invalid fields:\n{list}");
+            return Err(format!("Fix the invalid fields before compiling:\n{list}"));
         }
+        let final_nodes: Vec<usize> = workspace
+            .data
+            .nodes
+            .iter()
+            .filter(|n| n.desc.end)
+            .map(|n| n.id)
+            .collect();
+        if final_nodes.is_empty() {
+            return Err("Nothing to compile, the graph has no end node.".to_string());
+        }
+        Ok(Compiler::new(
+            self.shared.borrow().compile_debug_info,
+            self.shared.borrow().compile_optimize,
+            workspace.data.nodes.clone(),
+            workspace.data.connections.clone(),
+            workspace.data.desc_storage.custom_types(),
+            workspace.data.variables.clone(),
+            final_nodes,
+        ))
+    }
+
+    /// Compiles the open workspace, reusing the ops lowered by its last
+    /// compilation.
+    fn compile(&mut self) -> Result<Compilation, String> {
+        let compiler = self.compiler()?;
+        let workspace = self.workspace.as_mut().unwrap();
+        let mut compiler = compiler.with_cache(std::mem::take(&mut workspace.compile_cache));
+        let result = compiler.compile().map_err(|e| {
+            error!("Compilation failed: {e:?}");
+            format!("Compilation failed: {e:?}")
+        });
+        workspace.compile_cache = compiler.into_cache();
+        result
     }
 
     fn render_menu_bar(&mut self, ctx: &Context) {
@@ -165,14 +473,37 @@ impl App {
                         ui.label("Shift+O");
                         ui.end_row();
 
+                        let recent = self.session.recent.clone();
+                        ui.add_enabled_ui(!recent.is_empty(), |ui| {
+                            ui.menu_button("Open Recent", |ui| {
+                                for path in recent {
+                                    if ui.button(path.display().to_string()).clicked() {
+                                        self.open_workspace(path);
+                                        ui.close();
+                                    }
+                                }
+                                ui.separator();
+                                if ui.button("Clear").clicked() {
+                                    self.session.recent.clear();
+                                    ui.close();
+                                }
+                            });
+                        });
+                        ui.end_row();
+
                         if ui.button("Save").clicked() {
+                            self.save_or_pick();
+                            ui.close();
+                        }
+                        ui.label("Shift+S");
+                        ui.end_row();
+
+                        if ui.button("Save As…").clicked() {
                             if self.workspace.is_some() {
                                 self.picker = Some(FilePicker::new(DialogPurpose::SaveWorkspace));
                             }
-
                             ui.close();
                         }
-                        ui.label("Shift+S");
                         ui.end_row();
                     });
                 });
@@ -185,25 +516,35 @@ impl App {
                         }
                         ui.end_row();
 
+                        if ui.button("Library Paths").clicked() {
+                            self.show_library_paths = true;
+                            ui.close();
+                        }
+                        ui.end_row();
+
                         if ui.button("Import Std Libs").clicked() {
-                            self.workspace
-                                .as_mut()
-                                .unwrap()
-                                .data
-                                .desc_storage
-                                .import_std_libs()
-                                .unwrap_or_else(|e| {
-                                    error!("Failed to import std libs: {e:?}");
-                                    self.shared.borrow_mut().error =
-                                        Some(format!("Failed to import std libs: {e:?}"))
-                                });
+                            if let Some(workspace) = self.workspace.as_mut()
+                                && let Err(e) = workspace.import_std_libs()
+                            {
+                                error!("Failed to import std libs: {e:?}");
+                                self.shared.borrow_mut().error =
+                                    Some(format!("Failed to import std libs: {e:?}"))
+                            }
+                            ui.close();
+                        }
+                        ui.end_row();
+
+                        if ui.button("Variables").clicked() {
+                            if let Some(workspace) = self.workspace.as_mut() {
+                                workspace.show_variables = true;
+                            }
                             ui.close();
                         }
                         ui.end_row();
 
                         if ui.button("Add").clicked() {
-                            self.shared.borrow_mut().add_menu =
-                                Some((Pos2::new(100.0, 100.0), None));
+                            self.palette.open(Pos2::new(100.0, 100.0));
+                            self.link_from = None;
                             ui.close();
                         }
                         ui.label("Shift+A");
@@ -216,36 +557,31 @@ impl App {
                         &mut self.shared.borrow_mut().compile_debug_info,
                         "Include debug info",
                     );
+                    ui.checkbox(&mut self.shared.borrow_mut().compile_optimize, "Optimize");
+
+                    ui.checkbox(&mut self.watch.enabled, "Compile on change");
 
-                    let final_nodes = self
+                    let has_end = self
                         .workspace
                         .as_ref()
-                        .unwrap()
-                        .data
-                        .nodes
-                        .iter()
-                        .filter(|n| n.desc.end)
-                        .collect::<Vec<_>>();
-                    if ui
-                        .add_enabled(final_nodes.len() == 1, Button::new("Compile"))
-                        .clicked()
-                    {
-                        let mut compiler = Compiler::new(
-                            self.shared.borrow().compile_debug_info,
-                            self.workspace.as_ref().unwrap().data.nodes.clone(),
-                            self.workspace.as_ref().unwrap().data.connections.clone(),
-                            final_nodes[0].id,
-                        );
-                        match compiler.compile() {
+                        .is_some_and(|w| w.data.nodes.iter().any(|n| n.desc.end));
+                    if ui.add_enabled(has_end, Button::new("Compile")).clicked() {
+                        match self.compile() {
                             Ok(compilation) => {
                                 self.workspace.as_mut().unwrap().data.compilation =
                                     Some(compilation);
                             }
-                            Err(e) => {
-                                error!("Compilation failed: {e:?}");
-                                self.shared.borrow_mut().error =
-                                    Some(format!("Compilation failed: {e:?}"));
-                            }
+                            Err(e) => self.shared.borrow_mut().error = Some(e),
+                        }
+                        ui.close();
+                    }
+                    if ui
+                        .add_enabled(has_end, Button::new("Step through…"))
+                        .clicked()
+                    {
+                        match self.compiler() {
+                            Ok(compiler) => self.debugger = Some(Debugger::new(compiler)),
+                            Err(e) => self.shared.borrow_mut().error = Some(e),
                         }
                         ui.close();
                     }
@@ -256,6 +592,12 @@ impl App {
                         ui.close();
                     }
                 });
+
+                if let Some(path) = &self.session.path {
+                    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                        ui.weak(path.display().to_string());
+                    });
+                }
             });
         });
     }
@@ -270,79 +612,35 @@ impl eframe::App for App {
             self.shared.borrow_mut().cursor = pos;
         }
 
+        let mut import_paths = Vec::new();
+        let mut open_path = None;
+        let mut save_path = None;
         if let Some(picker) = &mut self.picker {
             picker.show(ctx);
             if let Some(path) = &picker.picked_path {
                 match picker.purpose {
-                    DialogPurpose::OpenWorkspace => {
-                        info!(?path, "Opening workspace");
-                        let compressed = path
-                            .extension()
-                            .map(|ext| ext == "no3zstd")
-                            .unwrap_or(false);
-                        match Workspace::load(self.shared.clone(), path.clone(), compressed) {
-                            Ok(ws) => {
-                                self.workspace = Some(ws);
-                            }
-                            Err(e) => {
-                                error!("Failed to open workspace: {e:?}");
-                                self.shared.borrow_mut().error =
-                                    Some(format!("Failed to open workspace: {e:?}"))
-                            }
-                        }
-                    }
-                    DialogPurpose::SaveWorkspace => {
-                        info!(?path, "Saving workspace");
-                        let compress = path
-                            .extension()
-                            .map(|ext| ext == "no3zstd")
-                            .unwrap_or(false);
-                        if self.workspace.is_none() {
-                            error!("No workspace to save.");
-                            self.shared.borrow_mut().error =
-                                Some("No workspace to save.".to_string());
-                        } else if let Err(e) = self
-                            .workspace
-                            .as_ref()
-                            .unwrap()
-                            .save(path.clone(), compress)
-                        {
-                            error!("Failed to save workspace: {e:?}");
-                            self.shared.borrow_mut().error =
-                                Some(format!("Failed to save workspace: {e:?}"));
-                        }
-                    }
+                    DialogPurpose::OpenWorkspace => open_path = Some(path.clone()),
+                    DialogPurpose::SaveWorkspace => save_path = Some(path.clone()),
                     DialogPurpose::ImportLibs => {
-                        let paths = &picker.picked_paths;
-                        info!(
-                            count = paths.as_ref().map(|p| p.len()).unwrap_or(0),
-                            "Importing libs"
-                        );
-                        for path in paths.as_ref().unwrap_or(&vec![]) {
-                            info!(?path, "Importing lib");
-                            if self.workspace.is_none() {
-                                self.shared.borrow_mut().error =
-                                    Some("No workspace to import lib into.".to_string());
-                            } else if let Err(e) = self
-                                .workspace
-                                .as_mut()
-                                .unwrap()
-                                .data
-                                .desc_storage
-                                .load_import(path.to_path_buf(), true)
-                            {
-                                self.shared.borrow_mut().error =
-                                    Some(format!("Failed to import lib: {e:?}"));
-                            }
-                        }
+                        import_paths = picker.picked_paths.clone().unwrap_or_default();
                     }
                     _ => {}
                 }
                 self.picker = None;
             }
         }
+        if let Some(path) = open_path {
+            self.open_workspace(path);
+        }
+        if let Some(path) = save_path {
+            self.save_workspace(path);
+        }
+        self.import_libs(import_paths);
 
+        let changed_libs = self.libraries.poll();
         if self.workspace.is_some() {
+            self.import_libs(changed_libs);
+
             if let Some((from_node, from_port, _)) =
                 self.workspace.as_ref().unwrap().dragging_connection
             {
@@ -350,9 +648,14 @@ impl eframe::App for App {
                     Some((from_node, from_port, self.shared.borrow().cursor));
             }
 
-            if input.key_pressed(egui::Key::A) && input.modifiers.shift {
+            if input.key_pressed(egui::Key::A)
+                && input.modifiers.shift
+                && !self.palette.is_open()
+                && !ctx.wants_keyboard_input()
+            {
                 let cursor = self.shared.borrow().cursor;
-                self.shared.borrow_mut().add_menu = Some((cursor, None));
+                self.palette.open(cursor);
+                self.link_from = None;
             }
 
             if ctx.input(|i| i.pointer.any_pressed())
@@ -402,6 +705,7 @@ impl eframe::App for App {
                         .data
                         .connections
                         .retain(|c| !(c.to.0 == node_id && c.to.1 == port_id));
+                    self.workspace.as_mut().unwrap().touch();
                     self.workspace.as_mut().unwrap().dragging_connection =
                         Some((from_node, from_port, self.shared.borrow().cursor));
                 }
@@ -410,23 +714,80 @@ impl eframe::App for App {
             if ctx.input(|i| i.pointer.any_released())
                 && let Some((from_node_id, from_port_id, current_pos)) =
                     self.workspace.as_mut().unwrap().dragging_connection.take()
-                && let Some((to_node_id, to_port_id, _)) = self
+            {
+                if let Some((to_node_id, to_port_id, _)) = self
                     .workspace
                     .as_ref()
                     .unwrap()
                     .mouse_over_port(current_pos, false)
-            {
-                self.workspace
-                    .as_mut()
-                    .unwrap()
-                    .data
-                    .connections
-                    .push(Connection {
-                        from: (from_node_id, from_port_id),
-                        to: (to_node_id, to_port_id),
-                    });
-                self.workspace.as_mut().unwrap().verify_connections();
+                {
+                    let workspace = self.workspace.as_ref().unwrap();
+                    if !workspace.connection_compatible(
+                        (from_node_id, from_port_id),
+                        (to_node_id, to_port_id),
+                    ) {
+                        self.shared.borrow_mut().error = Some(format!(
+                            "Cannot connect {:?} output to {} input",
+                            workspace.output_type(from_node_id, from_port_id),
+                            workspace.data.nodes[to_node_id].desc.inputs[to_port_id].data_type
+                        ));
+                    } else {
+                        self.workspace
+                            .as_mut()
+                            .unwrap()
+                            .data
+                            .connections
+                            .push(Connection {
+                                from: (from_node_id, from_port_id),
+                                to: (to_node_id, to_port_id),
+                            });
+                        self.workspace.as_mut().unwrap().verify_connections();
+                    }
+                } else if !ctx.is_pointer_over_area()
+                    && let Some(output_type) = self
+                        .workspace
+                        .as_ref()
+                        .unwrap()
+                        .output_type(from_node_id, from_port_id)
+                {
+                    self.palette.open_filtered(current_pos, output_type);
+                    self.link_from = Some((from_node_id, from_port_id));
+                }
+            }
+
+            if let Some(cache) = self.watch.receive() {
+                self.workspace.as_mut().unwrap().compile_cache = cache;
+            }
+            let start = self.watch.poll(ctx, || {
+                let shared = self.shared.borrow();
+                let revision = self.workspace.as_ref().unwrap().revision();
+                let mut hasher = DefaultHasher::new();
+                (revision, shared.compile_debug_info, shared.compile_optimize).hash(&mut hasher);
+                hasher.finish()
+            });
+            if start {
+                match self.compiler() {
+                    Ok(compiler) => {
+                        let workspace = self.workspace.as_mut().unwrap();
+                        let cache = std::mem::take(&mut workspace.compile_cache);
+                        self.watch.start(ctx, compiler.with_cache(cache));
+                    }
+                    Err(e) => self.watch.set_result(Err(e)),
+                }
+            }
+            self.watch.show(ctx);
+
+            if let Some(debugger) = &mut self.debugger {
+                debugger.show(ctx);
+            }
+            if self.debugger.as_ref().is_some_and(|d| !d.is_open()) {
+                self.debugger = None;
             }
+            self.workspace.as_mut().unwrap().execution_order = self
+                .debugger
+                .as_ref()
+                .map(|d| d.execution_order().to_vec())
+                .unwrap_or_default();
 
             CentralPanel::default().show(ctx, |_| {});
 
@@ -453,8 +814,22 @@ impl eframe::App for App {
             });
         }
 
+        self.render_library_paths(ctx);
         self.render_menu_bar(ctx);
+        self.render_recovery(ctx);
+        self.render_error(ctx);
 
         ctx.request_repaint();
     }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        // A recovery that was not answered yet is offered again next launch
+        self.session.snapshot = self.recovery.clone().or_else(|| self.snapshot());
+        self.session.recent_nodes = self.palette.recent().to_vec();
+        self.session.save(storage);
+    }
+
+    fn auto_save_interval(&self) -> Duration {
+        AUTOSAVE_INTERVAL
+    }
 }
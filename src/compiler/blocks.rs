@@ -0,0 +1,616 @@
+use anyhow::{anyhow, bail, Result};
+use std::collections::{HashMap, HashSet};
+
+use super::{IOMap, NodeMap};
+use crate::graph::{FieldKind, Node, NodeRole, Type, Var};
+
+/// Where the code of a node is written: at the top level of the program or in
+/// a block of a branching node, named by the `{b_<name>}` placeholder in its
+/// implementation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Root,
+    Block(usize, String), // (branching node, flow output or lazy input)
+}
+
+/// Names of the `{b_<name>}` placeholders in `code`.
+pub fn placeholders(code: &str) -> Vec<&str> {
+    code.split("{b_")
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+        .collect()
+}
+
+/// Whether the block `name` of `node` holds the nodes computing one of its
+/// inputs, which is then only evaluated when the node needs it. Other blocks
+/// belong to flow outputs.
+pub fn is_lazy_input(node: &Node, name: &str) -> bool {
+    !node
+        .desc
+        .outputs
+        .iter()
+        .any(|o| o.name == name && o.data_type == Type::Flow)
+        && node.desc.inputs.iter().any(|i| i.name == name)
+}
+
+/// Replaces the line holding the placeholder `{b_<name>}` with `code`, indented
+/// like the placeholder. Empty blocks become `pass`, unless more statements
+/// follow at the same indentation.
+pub fn fill(template: &str, name: &str, code: &str) -> String {
+    let placeholder = format!("{{b_{}}}", name);
+    let lines: Vec<&str> = template.lines().collect();
+    let mut output = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim() != placeholder {
+            output.push_str(line);
+            output.push('\n');
+            continue;
+        }
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let continued = lines
+            .get(i + 1)
+            .is_some_and(|next| next.starts_with(indent) && next.len() > indent.len());
+        if code.trim().is_empty() && !continued {
+            output.push_str(&format!("{}pass\n", indent));
+        }
+        for code_line in code.trim_end().lines() {
+            if !code_line.is_empty() {
+                output.push_str(indent);
+            }
+            output.push_str(code_line);
+            output.push('\n');
+        }
+    }
+    output
+}
+
+/// Places the nodes of an execution order into scopes. A node connected to a
+/// flow output runs in that output's block, every other node in the innermost
+/// scope shared by the nodes using its outputs, so branches only compute what
+/// they need. Nodes using an output that belongs to a block, like the item of
+/// a loop, are moved into that block.
+pub struct Blocks {
+    children: HashMap<Scope, Vec<usize>>,
+}
+
+impl Blocks {
+    pub fn new(exec_order: &[usize], node_map: &NodeMap, io_map: &IOMap) -> Result<Self> {
+        let mut placement = Placement {
+            max_depth: exec_order.len(),
+            gates: HashMap::new(),
+            floors: HashMap::new(),
+            inherited: HashMap::new(),
+            consumers: HashMap::new(),
+            scopes: HashMap::new(),
+            visiting: HashSet::new(),
+        };
+        // (producer, consumer) of every connection, flow or data
+        let mut edges: Vec<(usize, usize)> = Vec::new();
+        for &node_id in exec_order {
+            let Some(node) = node_map.get(node_id) else {
+                bail!("Node ID {} not found in NodeMap", node_id);
+            };
+            let blocks = node
+                .impl_for_lang("python3")
+                .map(|i| placeholders(&i.code))
+                .unwrap_or_default();
+            // Innermost block the node has to run in because of the outputs it uses
+            let mut inherited = Scope::Root;
+            for (i, input) in node.desc.inputs.iter().enumerate() {
+                let Some(&(from_node, from_port)) = io_map.get((node_id, i)) else {
+                    continue;
+                };
+                let Some(from) = node_map.get(from_node) else {
+                    bail!("Node ID {} not found in NodeMap", from_node);
+                };
+                let Some(output) = from.desc.outputs.get(from_port) else {
+                    bail!("Output port {} of node {} not found", from_port, from_node);
+                };
+                edges.push((from_node, node_id));
+                if input.data_type == Type::Flow {
+                    let gate = Scope::Block(from_node, output.name.clone());
+                    if placement.gates.insert(node_id, gate).is_some() {
+                        bail!(
+                            "{}#{} has more than one flow input connected",
+                            node.desc.title,
+                            node_id
+                        );
+                    }
+                    continue;
+                }
+
+                let mut required = vec![placement.inherited(from_node)];
+                if let Some(scope) = &output.scope {
+                    required.push(Scope::Block(from_node, scope.clone()));
+                }
+                // Inputs read in a block of the node may use values of that block
+                if let Some(scope) = &input.scope {
+                    let own = Scope::Block(node_id, scope.clone());
+                    required.retain(|r| !placement.floor_ancestors(r).contains(&own));
+                }
+                for required in required {
+                    inherited = placement.deeper(inherited, required).ok_or_else(|| {
+                        anyhow!(
+                            "{}#{} uses values from two unrelated blocks",
+                            node.desc.title,
+                            node_id
+                        )
+                    })?;
+                }
+
+                // Block of the node the input is read in, if any
+                let block = if let Some(scope) = &input.scope {
+                    Some(scope.clone())
+                } else if blocks.contains(&input.name.as_str()) && is_lazy_input(node, &input.name)
+                {
+                    Some(input.name.clone())
+                } else {
+                    None
+                };
+                placement
+                    .consumers
+                    .entry(from_node)
+                    .or_default()
+                    .push(Consumer {
+                        node: node_id,
+                        block,
+                        port: from_port,
+                        scoped: output.scope.is_some(),
+                    });
+            }
+            // End nodes run once per pass through the block of a scoped value
+            // they use, so that has to be asked for with a flow connection
+            if node.desc.end
+                && let Scope::Block(owner, name) = &inherited
+                && !placement
+                    .gates
+                    .get(&node_id)
+                    .is_some_and(|gate| placement.floor_ancestors(gate).contains(&inherited))
+            {
+                let owner_title = node_map.get(*owner).map_or("?", |n| n.desc.title.as_str());
+                bail!(
+                    "{}#{} uses a value scoped to {}#{} {}, connect it to {} to run it there",
+                    node.desc.title,
+                    node_id,
+                    owner_title,
+                    owner,
+                    name,
+                    name
+                );
+            }
+            let mut floor = inherited.clone();
+            if let Some(gate) = placement.gates.get(&node_id).cloned() {
+                floor = placement.deeper(floor, gate).ok_or_else(|| {
+                    anyhow!(
+                        "{}#{} is connected to a flow output outside the block it uses values from",
+                        node.desc.title,
+                        node_id
+                    )
+                })?;
+            }
+            placement.floors.insert(node_id, floor);
+            placement.inherited.insert(node_id, inherited);
+        }
+
+        // Nothing connects a Get to the Sets of its variable, so it reads after
+        // them, unless the value being set is computed from the read
+        let mut reads_after = Vec::new();
+        for &getter in exec_order {
+            let Some(name) = variable(node_map, getter, NodeRole::GetVariable) else {
+                continue;
+            };
+            for &setter in exec_order {
+                if variable(node_map, setter, NodeRole::SetVariable) == Some(name)
+                    && !reaches(&edges, getter, setter)
+                {
+                    reads_after.push((setter, getter));
+                }
+            }
+        }
+        edges.extend(reads_after);
+
+        for &node_id in exec_order {
+            placement.scope(node_id)?;
+        }
+        // A node must not be used outside the block it runs in, or the block
+        // the output belongs to
+        for &producer in exec_order {
+            let consumers = placement.consumers.get(&producer).cloned();
+            for consumer in consumers.into_iter().flatten() {
+                let location = placement.location(&consumer)?;
+                let port = consumer.port;
+                let ancestors = placement.ancestors(&location)?;
+                let node = &node_map.get(producer).unwrap().desc;
+                if !ancestors.contains(&placement.scope(producer)?) {
+                    bail!(
+                        "{}#{} runs in a branch but is used outside of it",
+                        node.title,
+                        producer
+                    );
+                }
+                if let Some(scope) = &node.outputs[port].scope
+                    && !ancestors.contains(&Scope::Block(producer, scope.clone()))
+                {
+                    bail!(
+                        "Output {} of {}#{} is used outside of {}",
+                        node.outputs[port].name,
+                        node.title,
+                        producer,
+                        scope
+                    );
+                }
+            }
+        }
+
+        // A node depends on the node in its scope that contains a producer of
+        // it or of anything in its blocks.
+        let mut deps: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (producer, consumer) in edges {
+            let mut node = consumer;
+            loop {
+                let scope = placement.scope(node)?;
+                if let Some(lifted) = placement.lift(producer, &scope)?
+                    && lifted != node
+                {
+                    deps.entry(node).or_default().push(lifted);
+                }
+                match scope {
+                    Scope::Root => break,
+                    Scope::Block(branch, _) => node = branch,
+                }
+            }
+        }
+
+        let mut children: HashMap<Scope, Vec<usize>> = HashMap::new();
+        let mut done = HashSet::new();
+        for &node_id in exec_order {
+            sort_recursive(
+                node_id,
+                &deps,
+                &placement.scopes,
+                &mut done,
+                &mut HashSet::new(),
+                &mut children,
+            )?;
+        }
+
+        Ok(Self { children })
+    }
+
+    /// Nodes written directly in `scope`, in the order they have to run.
+    pub fn children(&self, scope: &Scope) -> &[usize] {
+        self.children.get(scope).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+/// The workspace variable `node_id` reads or writes, if its role is `role`.
+fn variable(node_map: &NodeMap, node_id: usize, role: NodeRole) -> Option<&str> {
+    let node = node_map.get(node_id).filter(|n| n.desc.role == role)?;
+    node.desc
+        .fields
+        .iter()
+        .find(|f| matches!(f.kind, FieldKind::Variable))
+        .and_then(|f| match &f.value {
+            Var::String(name) => Some(name.as_str()),
+            _ => None,
+        })
+}
+
+/// Whether `to` uses the outputs of `from`, directly or through other nodes.
+fn reaches(edges: &[(usize, usize)], from: usize, to: usize) -> bool {
+    let mut stack = vec![from];
+    let mut seen = HashSet::new();
+    while let Some(node) = stack.pop() {
+        if node == to {
+            return true;
+        }
+        if seen.insert(node) {
+            stack.extend(edges.iter().filter(|(p, _)| *p == node).map(|(_, c)| *c));
+        }
+    }
+    false
+}
+
+fn sort_recursive(
+    node_id: usize,
+    deps: &HashMap<usize, Vec<usize>>,
+    scopes: &HashMap<usize, Scope>,
+    done: &mut HashSet<usize>,
+    visiting: &mut HashSet<usize>,
+    children: &mut HashMap<Scope, Vec<usize>>,
+) -> Result<()> {
+    if done.contains(&node_id) {
+        return Ok(());
+    }
+    if !visiting.insert(node_id) {
+        bail!("Node {} depends on a branch it is part of", node_id);
+    }
+    for &dep in deps.get(&node_id).into_iter().flatten() {
+        sort_recursive(dep, deps, scopes, done, visiting, children)?;
+    }
+    visiting.remove(&node_id);
+    done.insert(node_id);
+    children
+        .entry(scopes[&node_id].clone())
+        .or_default()
+        .push(node_id);
+    Ok(())
+}
+
+/// A node using an output of another node.
+#[derive(Clone)]
+struct Consumer {
+    node: usize,
+    block: Option<String>, // Block of `node` the output is read in, if any
+    port: usize,
+    scoped: bool, // Whether the output belongs to a block of the producer
+}
+
+struct Placement {
+    max_depth: usize,
+    gates: HashMap<usize, Scope>, // Blocks of the flow outputs nodes are connected to
+    floors: HashMap<usize, Scope>, // Innermost blocks nodes have to run in
+    inherited: HashMap<usize, Scope>, // Floors without the blocks of flow outputs
+    consumers: HashMap<usize, Vec<Consumer>>,
+    scopes: HashMap<usize, Scope>,
+    visiting: HashSet<usize>,
+}
+
+impl Placement {
+    fn scope(&mut self, node_id: usize) -> Result<Scope> {
+        if let Some(scope) = self.scopes.get(&node_id) {
+            return Ok(scope.clone());
+        }
+        if !self.visiting.insert(node_id) {
+            bail!("Node {} is nested in its own branch", node_id);
+        }
+
+        let scope = if let Some(gate) = self.gates.get(&node_id) {
+            gate.clone()
+        } else {
+            let consumers = self.consumers.get(&node_id).cloned().unwrap_or_default();
+            let mut common: Option<Vec<Scope>> = None;
+            // Nodes using outputs that belong to a block are in that block
+            for consumer in consumers.iter().filter(|c| !c.scoped) {
+                let location = self.location(consumer)?;
+                let ancestors = self.ancestors(&location)?;
+                common = Some(match common {
+                    None => ancestors,
+                    Some(common) => common
+                        .into_iter()
+                        .filter(|s| ancestors.contains(s))
+                        .collect(),
+                });
+            }
+            common
+                .and_then(|c| c.into_iter().next())
+                .unwrap_or(Scope::Root)
+        };
+        let floor = self.floor(node_id);
+        let scope = if self.ancestors(&scope)?.contains(&floor) {
+            scope
+        } else {
+            floor
+        };
+
+        self.visiting.remove(&node_id);
+        self.scopes.insert(node_id, scope.clone());
+        Ok(scope)
+    }
+
+    /// The scope `consumer` reads the output in.
+    fn location(&mut self, consumer: &Consumer) -> Result<Scope> {
+        match &consumer.block {
+            Some(name) => Ok(Scope::Block(consumer.node, name.clone())),
+            None => self.scope(consumer.node),
+        }
+    }
+
+    fn floor(&self, node_id: usize) -> Scope {
+        self.floors.get(&node_id).cloned().unwrap_or(Scope::Root)
+    }
+
+    /// The floor passed on to the nodes using the outputs of `node_id`. Being
+    /// connected to a flow output isn't passed on: the outputs of such a node
+    /// can't be used outside of the block at all.
+    fn inherited(&self, node_id: usize) -> Scope {
+        self.inherited.get(&node_id).cloned().unwrap_or(Scope::Root)
+    }
+
+    /// `scope` followed by the blocks it is at least nested in, going by the
+    /// floors of the nodes owning them.
+    fn floor_ancestors(&self, scope: &Scope) -> Vec<Scope> {
+        let mut ancestors = vec![scope.clone()];
+        let mut current = scope.clone();
+        while let Scope::Block(branch, _) = current {
+            if ancestors.len() > self.max_depth {
+                break;
+            }
+            current = self.floor(branch);
+            ancestors.push(current.clone());
+        }
+        ancestors
+    }
+
+    /// The inner one of two floors, or `None` if neither is nested in the other.
+    fn deeper(&self, a: Scope, b: Scope) -> Option<Scope> {
+        if self.floor_ancestors(&b).contains(&a) {
+            Some(b)
+        } else if self.floor_ancestors(&a).contains(&b) {
+            Some(a)
+        } else {
+            None
+        }
+    }
+
+    /// `scope` followed by the scopes it is nested in, innermost first.
+    fn ancestors(&mut self, scope: &Scope) -> Result<Vec<Scope>> {
+        let mut ancestors = vec![scope.clone()];
+        let mut current = scope.clone();
+        while let Scope::Block(branch, _) = current {
+            if ancestors.len() > self.max_depth {
+                bail!("Blocks are nested in a cycle");
+            }
+            current = self.scope(branch)?;
+            ancestors.push(current.clone());
+        }
+        Ok(ancestors)
+    }
+
+    /// The node directly in `scope` that `node_id` is, or is nested in.
+    fn lift(&mut self, node_id: usize, scope: &Scope) -> Result<Option<usize>> {
+        let mut node = node_id;
+        loop {
+            let node_scope = self.scope(node)?;
+            if &node_scope == scope {
+                return Ok(Some(node));
+            }
+            match node_scope {
+                Scope::Root => return Ok(None),
+                Scope::Block(branch, _) => node = branch,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fill, Blocks, Scope};
+    use crate::compiler::{IOMap, NodeMap};
+    use crate::graph::{Connection, DescStorage, Node, Var};
+
+    #[test]
+    fn test_fill() {
+        let template = "if c:\n    {b_then}\nelse:\n    {b_else}\n    x = 1\n";
+        let filled = fill(template, "then", "a = 1\n\nb = 2\n");
+        assert_eq!(
+            filled,
+            "if c:\n    a = 1\n\n    b = 2\nelse:\n    {b_else}\n    x = 1\n"
+        );
+        assert_eq!(fill("if c:\n    {b_then}", "then", ""), "if c:\n    pass\n");
+        assert_eq!(
+            fill(template, "else", ""),
+            "if c:\n    {b_then}\nelse:\n    x = 1\n"
+        );
+    }
+
+    #[test]
+    fn test_branches() {
+        let mut storage = DescStorage::new();
+        storage.import_std_libs().unwrap();
+        // Const 0 feeds If 1, Const 2 is only printed by Print 3 in the then branch.
+        let nodes = vec![
+            Node::new(&storage, 0, "Graph", "Input").unwrap(),
+            Node::new(&storage, 1, "Flow", "If").unwrap(),
+            Node::new(&storage, 2, "Math", "Const").unwrap(),
+            Node::new(&storage, 3, "Debug", "Print").unwrap(),
+        ];
+        let conns = vec![
+            Connection {
+                from: (0, 0),
+                to: (1, 0),
+            },
+            Connection {
+                from: (2, 0),
+                to: (3, 0),
+            },
+            Connection {
+                from: (1, 0),
+                to: (3, 1),
+            },
+        ];
+        let blocks =
+            Blocks::new(&[0, 1, 2, 3], &NodeMap::new(&nodes), &IOMap::new(&conns)).unwrap();
+        assert_eq!(blocks.children(&Scope::Root), &[0, 1]);
+        assert_eq!(
+            blocks.children(&Scope::Block(1, "then".to_string())),
+            &[2, 3]
+        );
+        assert!(blocks
+            .children(&Scope::Block(1, "else".to_string()))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_loop_body() {
+        let mut storage = DescStorage::new();
+        storage.import_std_libs().unwrap();
+        // Multiply 2 squares the items of For Each 1 and is printed by Print 3,
+        // which is connected to the body. Print 4 only uses the list, so stays
+        // outside.
+        let nodes = vec![
+            Node::new(&storage, 0, "Flow", "Range").unwrap(),
+            Node::new(&storage, 1, "Flow", "For Each").unwrap(),
+            Node::new(&storage, 2, "Math", "Multiply").unwrap(),
+            Node::new(&storage, 3, "Debug", "Print").unwrap(),
+            Node::new(&storage, 4, "Debug", "Print").unwrap(),
+        ];
+        let conn = |from, to| Connection { from, to };
+        let conns = vec![
+            conn((0, 0), (1, 0)),
+            conn((1, 1), (2, 0)),
+            conn((1, 1), (2, 1)),
+            conn((2, 0), (3, 0)),
+            conn((1, 0), (3, 1)),
+            conn((0, 0), (4, 0)),
+        ];
+        let blocks =
+            Blocks::new(&[0, 1, 2, 3, 4], &NodeMap::new(&nodes), &IOMap::new(&conns)).unwrap();
+        assert_eq!(blocks.children(&Scope::Root), &[0, 1, 4]);
+        assert_eq!(
+            blocks.children(&Scope::Block(1, "body".to_string())),
+            &[2, 3]
+        );
+
+        // Without the flow connection Print 3 would silently run in the loop
+        let conns: Vec<_> = conns.into_iter().filter(|c| c.to != (3, 1)).collect();
+        let error = Blocks::new(&[0, 1, 2, 3, 4], &NodeMap::new(&nodes), &IOMap::new(&conns))
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .contains("uses a value scoped to For Each#1 body"));
+    }
+
+    #[test]
+    fn test_variable_order() {
+        let mut storage = DescStorage::new();
+        storage.import_std_libs().unwrap();
+        let node = |id, category, title| {
+            let mut node = Node::new(&storage, id, category, title).unwrap();
+            if let Some(field) = node.desc.fields.first_mut() {
+                field.value = Var::String("x".to_string());
+            }
+            node
+        };
+        // Get 0 is printed by Print 1, Set 3 writes Const 2 to the same
+        // variable. Get 4 feeds Add 5 into Set 6, so it has to read first.
+        let nodes = vec![
+            node(0, "Variables", "Get"),
+            node(1, "Debug", "Print"),
+            node(2, "Math", "Const"),
+            node(3, "Variables", "Set"),
+            node(4, "Variables", "Get"),
+            node(5, "Math", "Add"),
+            node(6, "Variables", "Set"),
+        ];
+        let conn = |from, to| Connection { from, to };
+        let conns = vec![
+            conn((0, 0), (1, 0)),
+            conn((2, 0), (3, 0)),
+            conn((4, 0), (5, 0)),
+            conn((5, 0), (6, 0)),
+        ];
+        let blocks = Blocks::new(
+            &[0, 1, 2, 3, 4, 5, 6],
+            &NodeMap::new(&nodes),
+            &IOMap::new(&conns),
+        )
+        .unwrap();
+        let order = blocks.children(&Scope::Root);
+        let position = |id| order.iter().position(|&n| n == id).unwrap();
+        assert!(position(3) < position(0));
+        assert!(position(0) < position(1));
+        assert!(position(4) < position(6));
+    }
+}
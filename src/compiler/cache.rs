@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+
+use super::ir::Op;
+
+/// Ops lowered by earlier compilations, by node ID. An op is reused as long as
+/// the key of everything its lowering reads stays the same, see
+/// `lower::cache_key`.
+#[derive(Default)]
+pub struct NodeCache {
+    entries: HashMap<usize, Entry>,
+    hits: usize,
+}
+
+struct Entry {
+    key: u64,
+    op: Op,
+    warnings: Vec<String>, // Reported again whenever the op is reused
+}
+
+impl NodeCache {
+    pub fn get(&mut self, node: usize, key: u64) -> Option<(&Op, &[String])> {
+        let entry = self.entries.get(&node).filter(|e| e.key == key)?;
+        self.hits += 1;
+        Some((&entry.op, &entry.warnings))
+    }
+
+    pub fn insert(&mut self, node: usize, key: u64, op: Op, warnings: Vec<String>) {
+        self.entries.insert(node, Entry { key, op, warnings });
+    }
+
+    /// Drops the ops of nodes other than `nodes`, e.g. deleted ones.
+    pub fn retain(&mut self, nodes: &[usize]) {
+        self.entries.retain(|node, _| nodes.contains(node));
+    }
+
+    /// Number of ops reused since the cache was created.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+}
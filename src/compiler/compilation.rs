@@ -1,9 +1,16 @@
 use chrono::{DateTime, Utc};
-use eframe::egui::{Context, Window};
+use eframe::egui::{CollapsingHeader, Color32, Context, ScrollArea, TextEdit, Window};
 use egui_code_editor::{CodeEditor, ColorTheme, Syntax};
 use serde::{Deserialize, Serialize};
-use std::{cell::RefCell, process::Command, rc::Rc, time::Duration};
+use std::{
+    path::Path,
+    process::Command,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{error, info};
 
+use super::{export, Diagnostic, Manifest};
 use crate::file_picker::{DialogPurpose, FilePicker};
 
 pub const AYU_DARK: ColorTheme = ColorTheme {
@@ -28,8 +35,22 @@ pub struct Compilation {
     pub code: String,
     pub timestamp: DateTime<Utc>,
     pub elapsed_times: Vec<(String, Duration)>,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+    #[serde(default)]
+    pub ir: String, // Intermediate representation the code was written from
+    #[serde(default)]
+    pub manifest: Manifest,
+    #[serde(skip)]
+    save_picker: Arc<Mutex<Option<FilePicker>>>,
+    #[serde(skip)]
+    export_picker: Arc<Mutex<Option<FilePicker>>>,
     #[serde(skip)]
-    save_picker: Rc<RefCell<Option<FilePicker>>>,
+    export_workspace: bool, // Copy the workspace into the exported project
+    #[serde(skip)]
+    export_status: Option<Result<String, String>>,
     run_result: Option<String>,
 }
 
@@ -39,7 +60,14 @@ impl Compilation {
             code: String::new(),
             timestamp: Utc::now(),
             elapsed_times: Vec::new(),
-            save_picker: Rc::new(RefCell::new(None)),
+            warnings: Vec::new(),
+            diagnostics: Vec::new(),
+            ir: String::new(),
+            manifest: Manifest::default(),
+            save_picker: Arc::new(Mutex::new(None)),
+            export_picker: Arc::new(Mutex::new(None)),
+            export_workspace: false,
+            export_status: None,
             run_result: None,
         }
     }
@@ -52,9 +80,15 @@ impl Compilation {
         self.code = code;
     }
 
-    pub fn update(&mut self, ctx: &Context) {
+    /// Shows the compilation window. `workspace` serializes the workspace as
+    /// saved to a directory, for exports that include it.
+    pub fn update(
+        &mut self,
+        ctx: &Context,
+        workspace: impl FnOnce(&Path) -> anyhow::Result<String>,
+    ) {
         let mut reset_picker = false;
-        if let Some(picker) = self.save_picker.borrow_mut().as_mut() {
+        if let Some(picker) = self.save_picker.lock().unwrap().as_mut() {
             picker.show(ctx);
             if let Some(path) = &picker.picked_path {
                 if let Err(e) = std::fs::write(path, &self.code) {
@@ -65,7 +99,31 @@ impl Compilation {
             }
         }
         if reset_picker {
-            *self.save_picker.borrow_mut() = None;
+            *self.save_picker.lock().unwrap() = None;
+        }
+
+        let mut export_dir = None;
+        if let Some(picker) = self.export_picker.lock().unwrap().as_mut() {
+            picker.show(ctx);
+            export_dir = picker.picked_path.clone();
+        }
+        if let Some(dir) = export_dir {
+            *self.export_picker.lock().unwrap() = None;
+            let result = if self.export_workspace {
+                workspace(&dir).and_then(|ron| export(&dir, self, Some(ron)))
+            } else {
+                export(&dir, self, None)
+            };
+            self.export_status = Some(match result {
+                Ok(()) => {
+                    info!(?dir, "Exported project");
+                    Ok(format!("Exported to {}", dir.display()))
+                }
+                Err(e) => {
+                    error!("Failed to export project: {e:?}");
+                    Err(format!("Failed to export project: {e:?}"))
+                }
+            });
         }
 
         Window::new("Compilation").resizable(true).show(ctx, |ui| {
@@ -79,10 +137,16 @@ impl Compilation {
 
             ui.horizontal(|ui| {
                 if ui.button("Save").clicked() {
-                    *self.save_picker.borrow_mut() =
+                    *self.save_picker.lock().unwrap() =
                         Some(FilePicker::new(DialogPurpose::SavePython));
                 }
 
+                if ui.button("Export").clicked() {
+                    *self.export_picker.lock().unwrap() =
+                        Some(FilePicker::new(DialogPurpose::ExportProject));
+                }
+                ui.checkbox(&mut self.export_workspace, "with workspace");
+
                 if ui.button("Run").clicked() {
                     let output = Command::new("python").arg("-c").arg(&self.code).output();
                     self.run_result = match output {
@@ -144,6 +208,39 @@ impl Compilation {
                 }
             });
 
+            match &self.export_status {
+                Some(Ok(message)) => {
+                    ui.label(message);
+                }
+                Some(Err(error)) => {
+                    ui.colored_label(Color32::LIGHT_RED, error);
+                }
+                None => {}
+            }
+
+            for warning in &self.warnings {
+                ui.colored_label(Color32::YELLOW, format!("⚠ {}", warning));
+            }
+            for diagnostic in &self.diagnostics {
+                if diagnostic.is_excluded() {
+                    ui.colored_label(Color32::YELLOW, format!("⚠ {}", diagnostic.message));
+                } else {
+                    ui.colored_label(Color32::GRAY, format!("ℹ {}", diagnostic.message));
+                }
+            }
+
+            if !self.ir.is_empty() {
+                CollapsingHeader::new("IR").show(ui, |ui| {
+                    ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                        ui.add(
+                            TextEdit::multiline(&mut self.ir.as_str())
+                                .code_editor()
+                                .desired_width(f32::INFINITY),
+                        );
+                    });
+                });
+            }
+
             ui.add_space(6.0);
 
             CodeEditor::default()
@@ -0,0 +1,244 @@
+use eframe::egui::{
+    Button, CollapsingHeader, Color32, Context, Grid, RichText, ScrollArea, TextEdit, Ui, Window,
+};
+
+use super::ir::ValueId;
+use super::{Compiler, IOMap, NodeMap, Stage, TypeMap};
+
+/// Steps a compiler through its stages and shows what each one produced, for
+/// library authors to see how the graph turns into code.
+pub struct Debugger {
+    compiler: Compiler,
+    open: bool,
+    error: Option<String>, // The step failed, the compiler can't go on
+    node_map: Option<NodeMap>,
+    io_map: Option<IOMap>,
+    execution_order: Vec<usize>,
+    types: Option<TypeMap<ValueId>>, // Resolved while lowering, before optimizing
+    ir: String,
+    code: String,
+}
+
+impl Debugger {
+    pub fn new(compiler: Compiler) -> Self {
+        Self {
+            compiler,
+            open: true,
+            error: None,
+            node_map: None,
+            io_map: None,
+            execution_order: Vec::new(),
+            types: None,
+            ir: String::new(),
+            code: String::new(),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Nodes in the order the compiled program runs them, once traversed.
+    pub fn execution_order(&self) -> &[usize] {
+        &self.execution_order
+    }
+
+    fn can_step(&self) -> bool {
+        self.error.is_none() && !matches!(self.compiler.stage(), Stage::Finished(_))
+    }
+
+    fn step(&mut self) {
+        if let Err(e) = self.compiler.step() {
+            self.error = Some(format!("{e:?}"));
+            return;
+        }
+        match self.compiler.stage() {
+            Stage::Raw { .. } => {}
+            Stage::Maps { node_map, io_map } => {
+                self.node_map = Some(node_map.clone());
+                self.io_map = Some(io_map.clone());
+            }
+            Stage::Traversal { traversal, .. } => {
+                self.execution_order = traversal.execution_order().clone();
+            }
+            Stage::Lowered(ir) => {
+                self.types = Some(ir.types());
+                self.ir = ir.to_string();
+            }
+            Stage::Optimized(ir) => self.ir = ir.to_string(),
+            Stage::Finished(code) => self.code = code.clone(),
+        }
+    }
+
+    pub fn show(&mut self, ctx: &Context) {
+        let mut open = self.open;
+        Window::new("Compiler Debugger")
+            .open(&mut open)
+            .resizable(true)
+            .default_width(480.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Stage:");
+                    ui.strong(self.compiler.stage().to_string());
+                    if ui
+                        .add_enabled(self.can_step(), Button::new("Step"))
+                        .clicked()
+                    {
+                        self.step();
+                    }
+                    if ui
+                        .add_enabled(self.can_step(), Button::new("Run to end"))
+                        .clicked()
+                    {
+                        while self.can_step() {
+                            self.step();
+                        }
+                    }
+                });
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::LIGHT_RED, error);
+                }
+                for warning in &self.compiler.compilation().warnings {
+                    ui.colored_label(Color32::YELLOW, format!("⚠ {}", warning));
+                }
+                for diagnostic in &self.compiler.compilation().diagnostics {
+                    ui.colored_label(Color32::GRAY, format!("ℹ {}", diagnostic.message));
+                }
+                ui.separator();
+
+                ScrollArea::vertical().show(ui, |ui| {
+                    if let Some(node_map) = &self.node_map {
+                        CollapsingHeader::new("NodeMap").show(ui, |ui| node_map_grid(ui, node_map));
+                    }
+                    if let (Some(node_map), Some(io_map)) = (&self.node_map, &self.io_map) {
+                        CollapsingHeader::new("IOMap")
+                            .show(ui, |ui| io_map_grid(ui, node_map, io_map));
+                    }
+                    if let Some(node_map) = &self.node_map
+                        && !self.execution_order.is_empty()
+                    {
+                        CollapsingHeader::new("Execution order").show(ui, |ui| {
+                            for (step, id) in self.execution_order.iter().enumerate() {
+                                ui.label(format!("{}. {}", step + 1, node_name(node_map, *id)));
+                            }
+                        });
+                    }
+                    if let (Some(node_map), Some(io_map), Some(types)) =
+                        (&self.node_map, &self.io_map, &self.types)
+                    {
+                        CollapsingHeader::new("TypeMap").show(ui, |ui| {
+                            for id in &self.execution_order {
+                                node_types(ui, node_map, io_map, types, *id);
+                            }
+                        });
+                    }
+                    if !self.ir.is_empty() {
+                        CollapsingHeader::new("IR").show(ui, |ui| code_view(ui, &self.ir));
+                    }
+                    if !self.code.is_empty() {
+                        CollapsingHeader::new("Code")
+                            .default_open(true)
+                            .show(ui, |ui| code_view(ui, &self.code));
+                    }
+                });
+            });
+        self.open = open;
+    }
+}
+
+fn node_name(node_map: &NodeMap, id: usize) -> String {
+    match node_map.get(id) {
+        Some(node) => format!("{}#{}", node.desc.title, id),
+        None => format!("?#{}", id),
+    }
+}
+
+fn port_name(node_map: &NodeMap, (id, port): (usize, usize), output: bool) -> String {
+    let ports = node_map.get(id).map(|node| {
+        if output {
+            &node.desc.outputs
+        } else {
+            &node.desc.inputs
+        }
+    });
+    match ports.and_then(|ports| ports.get(port)) {
+        Some(desc) => format!("{}.{}", node_name(node_map, id), desc.name),
+        None => format!("{}.{}", node_name(node_map, id), port),
+    }
+}
+
+fn node_map_grid(ui: &mut Ui, node_map: &NodeMap) {
+    Grid::new("debugger_node_map").striped(true).show(ui, |ui| {
+        for id in node_map.ids() {
+            let Some(node) = node_map.get(id) else {
+                continue;
+            };
+            ui.label(node_name(node_map, id));
+            ui.weak(format!(
+                "{} {}",
+                node.desc_ref.category, node.desc_ref.version
+            ));
+            ui.end_row();
+        }
+    });
+}
+
+fn io_map_grid(ui: &mut Ui, node_map: &NodeMap, io_map: &IOMap) {
+    Grid::new("debugger_io_map").striped(true).show(ui, |ui| {
+        for (to, from) in io_map.connections() {
+            ui.label(port_name(node_map, from, true));
+            ui.label("→");
+            ui.label(port_name(node_map, to, false));
+            ui.end_row();
+        }
+    });
+}
+
+/// Declared and resolved types of the ports of node `id`.
+fn node_types(
+    ui: &mut Ui,
+    node_map: &NodeMap,
+    io_map: &IOMap,
+    types: &TypeMap<ValueId>,
+    id: usize,
+) {
+    let Some(node) = node_map.get(id) else {
+        return;
+    };
+    ui.label(RichText::new(node_name(node_map, id)).strong());
+    Grid::new(("debugger_types", id)).show(ui, |ui| {
+        for (i, input) in node.desc.inputs.iter().enumerate() {
+            let resolved = match io_map.get((id, i)) {
+                Some(from) => types
+                    .get(*from)
+                    .map(|t| format!("{} from {}", t, port_name(node_map, *from, true)))
+                    .unwrap_or_else(|| format!("from {}", port_name(node_map, *from, true))),
+                None => "unconnected".to_string(),
+            };
+            ui.weak("in");
+            ui.label(&input.name);
+            ui.label(input.data_type.to_string());
+            ui.label(resolved);
+            ui.end_row();
+        }
+        for (i, output) in node.desc.outputs.iter().enumerate() {
+            let resolved = types
+                .get((id, i))
+                .map(|t| t.to_string())
+                .unwrap_or_default();
+            ui.weak("out");
+            ui.label(&output.name);
+            ui.label(output.data_type.to_string());
+            ui.label(resolved);
+            ui.end_row();
+        }
+    });
+}
+
+fn code_view(ui: &mut Ui, code: &str) {
+    ui.add(
+        TextEdit::multiline(&mut &*code)
+            .code_editor()
+            .desired_width(f32::INFINITY),
+    );
+}
@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+
+use super::{IOMap, NodeMap};
+use crate::graph::Type;
+
+/// Something the user should know about a graph that still compiles.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub node: usize,
+    pub kind: DiagnosticKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiagnosticKind {
+    Unreachable,         // Not upstream of an end node, so not compiled
+    UnusedOutput(usize), // Computed, but nothing is connected to it
+    IgnoredEnd,          // End node with unconnected inputs
+}
+
+impl Diagnostic {
+    /// Whether the node is left out of the compiled program.
+    pub fn is_excluded(&self) -> bool {
+        matches!(
+            self.kind,
+            DiagnosticKind::Unreachable | DiagnosticKind::IgnoredEnd
+        )
+    }
+}
+
+/// Splits `final_nodes` into the end nodes that can be compiled and
+/// diagnostics for the ones whose inputs aren't all connected.
+pub fn end_nodes(
+    final_nodes: &[usize],
+    node_map: &NodeMap,
+    io_map: &IOMap,
+) -> (Vec<usize>, Vec<Diagnostic>) {
+    let mut compiled = Vec::new();
+    let mut diagnostics = Vec::new();
+    for &node_id in final_nodes {
+        let Some(node) = node_map.get(node_id) else {
+            continue;
+        };
+        let unconnected: Vec<&str> = node
+            .desc
+            .inputs
+            .iter()
+            .enumerate()
+            .filter(|(i, input)| {
+                input.data_type != Type::Flow && io_map.get((node_id, *i)).is_none()
+            })
+            .map(|(_, input)| input.name.as_str())
+            .collect();
+        if unconnected.is_empty() {
+            compiled.push(node_id);
+        } else {
+            diagnostics.push(Diagnostic {
+                node: node_id,
+                kind: DiagnosticKind::IgnoredEnd,
+                message: format!(
+                    "{}#{} is ignored, its input {} is not connected",
+                    node.desc.title,
+                    node_id,
+                    unconnected.join(", ")
+                ),
+            });
+        }
+    }
+    (compiled, diagnostics)
+}
+
+/// Diagnostics for the nodes missing from `exec_order`, other than `ignored`,
+/// and the outputs of compiled nodes that nothing uses. Outputs of end nodes
+/// are left out, as those nodes run for their side effects.
+pub fn unused(
+    node_map: &NodeMap,
+    io_map: &IOMap,
+    exec_order: &[usize],
+    ignored: &[usize],
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for node_id in node_map.ids() {
+        let node = node_map.get(node_id).unwrap();
+        if !exec_order.contains(&node_id) {
+            if !ignored.contains(&node_id) {
+                diagnostics.push(Diagnostic {
+                    node: node_id,
+                    kind: DiagnosticKind::Unreachable,
+                    message: format!(
+                        "{}#{} is not connected to an end node and is not compiled",
+                        node.desc.title, node_id
+                    ),
+                });
+            }
+            continue;
+        }
+        if node.desc.end {
+            continue;
+        }
+        for (port, output) in node.desc.outputs.iter().enumerate() {
+            if output.data_type != Type::Flow && !io_map.is_used((node_id, port)) {
+                diagnostics.push(Diagnostic {
+                    node: node_id,
+                    kind: DiagnosticKind::UnusedOutput(port),
+                    message: format!(
+                        "Output {} of {}#{} is not used",
+                        output.name, node.desc.title, node_id
+                    ),
+                });
+            }
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod test {
+    use super::{end_nodes, unused, DiagnosticKind};
+    use crate::compiler::{IOMap, NodeMap, UpstreamTraversal};
+    use crate::graph::{Connection, DescStorage, Node};
+
+    #[test]
+    fn test_diagnostics() {
+        let mut storage = DescStorage::new();
+        storage.import_std_libs().unwrap();
+        // Print 2 prints the items of For Each 1, whose index is unused. Const 3
+        // isn't connected and Print 4 has nothing to print.
+        let nodes = vec![
+            Node::new(&storage, 0, "Flow", "Range").unwrap(),
+            Node::new(&storage, 1, "Flow", "For Each").unwrap(),
+            Node::new(&storage, 2, "Debug", "Print").unwrap(),
+            Node::new(&storage, 3, "Math", "Const").unwrap(),
+            Node::new(&storage, 4, "Debug", "Print").unwrap(),
+        ];
+        let conn = |from, to| Connection { from, to };
+        let conns = vec![conn((0, 0), (1, 0)), conn((1, 1), (2, 0))];
+        let node_map = NodeMap::new(&nodes);
+        let io_map = IOMap::new(&conns);
+
+        let (compiled, ignored) = end_nodes(&[2, 4], &node_map, &io_map);
+        assert_eq!(compiled, vec![2]);
+        assert_eq!(ignored.len(), 1);
+        assert_eq!(ignored[0].kind, DiagnosticKind::IgnoredEnd);
+
+        let mut traversal = UpstreamTraversal::new();
+        traversal.traverse(2, &node_map, &io_map);
+        let diagnostics = unused(&node_map, &io_map, traversal.execution_order(), &[4]);
+        let kinds: Vec<(usize, DiagnosticKind)> =
+            diagnostics.iter().map(|d| (d.node, d.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (1, DiagnosticKind::UnusedOutput(2)),
+                (3, DiagnosticKind::Unreachable)
+            ]
+        );
+    }
+}
@@ -0,0 +1,425 @@
+use crate::graph::Var;
+
+/// Evaluates a constant Python expression, following Python's semantics.
+///
+/// Only literals, arithmetic, comparisons, `not` and the `int`, `float` and
+/// `str` conversions are supported. Returns `None` for anything else, or when
+/// Python would raise an error, so the expression is left for Python to run.
+pub fn evaluate(expr: &str) -> Option<Var> {
+    let tokens = tokenize(expr)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let value = parser.not_expr()?;
+    (parser.pos == parser.tokens.len()).then_some(value)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Value(Var),
+    Name(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 15] = [
+    "**", "//", "==", "!=", "<=", ">=", "+", "-", "*", "/", "%", "<", ">", "(", ")",
+];
+
+fn tokenize(expr: &str) -> Option<Vec<Token>> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit()))
+        {
+            let start = i;
+            let mut float = false;
+            while i < chars.len() {
+                match chars[i] {
+                    '0'..='9' => {}
+                    '.' => float = true,
+                    'e' | 'E' => {
+                        float = true;
+                        if matches!(chars.get(i + 1), Some('+' | '-')) {
+                            i += 1;
+                        }
+                    }
+                    _ => break,
+                }
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(Token::Value(if float {
+                Var::Float(text.parse().ok()?)
+            } else {
+                Var::Int(text.parse().ok()?)
+            }));
+        } else if c == '"' || c == '\'' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match *chars.get(i)? {
+                    q if q == c => break,
+                    '\\' => {
+                        i += 1;
+                        value.push(match *chars.get(i)? {
+                            'n' => '\n',
+                            'r' => '\r',
+                            't' => '\t',
+                            e @ ('\\' | '"' | '\'') => e,
+                            _ => return None,
+                        });
+                    }
+                    other => value.push(other),
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push(Token::Value(Var::String(value)));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(match chars[start..i].iter().collect::<String>().as_str() {
+                "True" => Token::Value(Var::Bool(true)),
+                "False" => Token::Value(Var::Bool(false)),
+                name => Token::Name(name.to_string()),
+            });
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let symbol = SYMBOLS.iter().find(|s| rest.starts_with(**s))?;
+            i += symbol.len();
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+    Some(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// Consumes the next token if it is one of `symbols`.
+    fn symbol(&mut self, symbols: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Symbol(s)) if symbols.contains(s) => {
+                let s = *s;
+                self.pos += 1;
+                Some(s)
+            }
+            _ => None,
+        }
+    }
+
+    fn not_expr(&mut self) -> Option<Var> {
+        if self.peek() == Some(&Token::Name("not".to_string())) {
+            self.pos += 1;
+            return Some(Var::Bool(!truthy(&self.not_expr()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Option<Var> {
+        let mut left = self.arith()?;
+        let mut result = None;
+        while let Some(op) = self.symbol(&["==", "!=", "<", "<=", ">", ">="]) {
+            let right = self.arith()?;
+            let holds = compare(op, &left, &right)?;
+            result = Some(result.unwrap_or(true) && holds);
+            left = right;
+        }
+        Some(result.map_or(left, Var::Bool))
+    }
+
+    fn arith(&mut self) -> Option<Var> {
+        let mut left = self.term()?;
+        while let Some(op) = self.symbol(&["+", "-"]) {
+            left = binary(op, left, self.term()?)?;
+        }
+        Some(left)
+    }
+
+    fn term(&mut self) -> Option<Var> {
+        let mut left = self.factor()?;
+        while let Some(op) = self.symbol(&["*", "/", "//", "%"]) {
+            left = binary(op, left, self.factor()?)?;
+        }
+        Some(left)
+    }
+
+    fn factor(&mut self) -> Option<Var> {
+        match self.symbol(&["-", "+"]) {
+            Some("-") => match number(self.factor()?)? {
+                Number::Int(i) => Some(Var::Int(i.checked_neg()?)),
+                Number::Float(f) => Some(Var::Float(-f)),
+            },
+            Some(_) => match number(self.factor()?)? {
+                Number::Int(i) => Some(Var::Int(i)),
+                Number::Float(f) => Some(Var::Float(f)),
+            },
+            None => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Option<Var> {
+        let base = self.atom()?;
+        if self.symbol(&["**"]).is_some() {
+            return binary("**", base, self.factor()?);
+        }
+        Some(base)
+    }
+
+    fn atom(&mut self) -> Option<Var> {
+        match self.peek()?.clone() {
+            Token::Value(value) => {
+                self.pos += 1;
+                Some(value)
+            }
+            Token::Symbol("(") => {
+                self.pos += 1;
+                let value = self.not_expr()?;
+                self.symbol(&[")"])?;
+                Some(value)
+            }
+            Token::Name(name) => {
+                self.pos += 1;
+                self.symbol(&["("])?;
+                let argument = self.not_expr()?;
+                self.symbol(&[")"])?;
+                convert(&name, argument)
+            }
+            Token::Symbol(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+/// The number a value stands for in arithmetic, with `bool` as `int`.
+fn number(value: Var) -> Option<Number> {
+    match value {
+        Var::Bool(b) => Some(Number::Int(b as i64)),
+        Var::Int(i) => Some(Number::Int(i)),
+        Var::Float(f) => Some(Number::Float(f)),
+        _ => None,
+    }
+}
+
+/// Integers beyond 2^53 don't convert to floats exactly.
+fn to_float(number: Number) -> Option<f64> {
+    match number {
+        Number::Int(i) if i.unsigned_abs() <= 1 << 53 => Some(i as f64),
+        Number::Int(_) => None,
+        Number::Float(f) => Some(f),
+    }
+}
+
+fn truthy(value: &Var) -> bool {
+    match value {
+        Var::Bool(b) => *b,
+        Var::Int(i) => *i != 0,
+        Var::Float(f) => *f != 0.0,
+        Var::String(s) => !s.is_empty(),
+        _ => true,
+    }
+}
+
+fn binary(op: &str, left: Var, right: Var) -> Option<Var> {
+    match (left, right) {
+        (Var::String(a), Var::String(b)) if op == "+" => Some(Var::String(a + &b)),
+        (Var::String(s), n) | (n, Var::String(s)) if op == "*" => {
+            let Number::Int(times) = number(n)? else {
+                return None;
+            };
+            // Large strings are better built at run time
+            let times = usize::try_from(times.max(0)).ok()?;
+            (s.len().saturating_mul(times) <= 1000).then(|| Var::String(s.repeat(times)))
+        }
+        (left, right) => match (number(left)?, number(right)?) {
+            (Number::Int(a), Number::Int(b)) => int_binary(op, a, b),
+            (a, b) => float_binary(op, to_float(a)?, to_float(b)?).map(Var::Float),
+        },
+    }
+}
+
+fn int_binary(op: &str, a: i64, b: i64) -> Option<Var> {
+    let result = match op {
+        "+" => a.checked_add(b)?,
+        "-" => a.checked_sub(b)?,
+        "*" => a.checked_mul(b)?,
+        "/" if b == 0 => return None,
+        "/" => {
+            let (a, b) = (to_float(Number::Int(a))?, to_float(Number::Int(b))?);
+            return Some(Var::Float(a / b));
+        }
+        "//" | "%" if b == 0 => return None,
+        "//" => {
+            let quotient = a.checked_div(b)?;
+            if a % b != 0 && (a < 0) != (b < 0) {
+                quotient - 1
+            } else {
+                quotient
+            }
+        }
+        "%" => {
+            let remainder = a.checked_rem(b)?;
+            if remainder != 0 && (remainder < 0) != (b < 0) {
+                remainder + b
+            } else {
+                remainder
+            }
+        }
+        "**" if b < 0 => {
+            let a = to_float(Number::Int(a))?;
+            return float_binary(op, a, b as f64).map(Var::Float);
+        }
+        "**" => a.checked_pow(u32::try_from(b).ok()?)?,
+        _ => return None,
+    };
+    Some(Var::Int(result))
+}
+
+fn float_binary(op: &str, a: f64, b: f64) -> Option<f64> {
+    match op {
+        "+" => Some(a + b),
+        "-" => Some(a - b),
+        "*" => Some(a * b),
+        "/" | "//" | "%" if b == 0.0 => None,
+        "/" => Some(a / b),
+        // CPython's float_divmod
+        "//" | "%" => {
+            let mut modulo = a % b;
+            let mut div = (a - modulo) / b;
+            if modulo != 0.0 {
+                if (b < 0.0) != (modulo < 0.0) {
+                    modulo += b;
+                    div -= 1.0;
+                }
+            } else {
+                modulo = 0.0f64.copysign(b);
+            }
+            let floordiv = if div != 0.0 {
+                let floor = div.floor();
+                if div - floor > 0.5 {
+                    floor + 1.0
+                } else {
+                    floor
+                }
+            } else {
+                0.0f64.copysign(a / b)
+            };
+            Some(if op == "//" { floordiv } else { modulo })
+        }
+        "**" => {
+            // Python raises for these, or returns a complex number
+            if (a == 0.0 && b < 0.0) || (a < 0.0 && b.fract() != 0.0) {
+                return None;
+            }
+            let result = a.powf(b);
+            (result.is_finite() || !a.is_finite() || !b.is_finite()).then_some(result)
+        }
+        _ => None,
+    }
+}
+
+fn compare(op: &str, left: &Var, right: &Var) -> Option<bool> {
+    let ordering = match (left, right) {
+        (Var::String(a), Var::String(b)) => Some(a.cmp(b)),
+        (Var::String(_), _) | (_, Var::String(_)) => {
+            return match op {
+                "==" => Some(false),
+                "!=" => Some(true),
+                _ => None,
+            };
+        }
+        (left, right) => match (number(left.clone())?, number(right.clone())?) {
+            (Number::Int(a), Number::Int(b)) => Some(a.cmp(&b)),
+            (a, b) => to_float(a)?.partial_cmp(&to_float(b)?),
+        },
+    };
+    // `None` is a comparison with NaN, which only `!=` holds for
+    let Some(ordering) = ordering else {
+        return Some(op == "!=");
+    };
+    Some(match op {
+        "==" => ordering.is_eq(),
+        "!=" => ordering.is_ne(),
+        "<" => ordering.is_lt(),
+        "<=" => ordering.is_le(),
+        ">" => ordering.is_gt(),
+        ">=" => ordering.is_ge(),
+        _ => return None,
+    })
+}
+
+fn convert(function: &str, value: Var) -> Option<Var> {
+    match (function, value) {
+        ("int", Var::String(s)) => {
+            let digits = s.trim().trim_start_matches(['+', '-']);
+            if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            s.trim().parse().ok().map(Var::Int)
+        }
+        ("int", value) => match number(value)? {
+            Number::Int(i) => Some(Var::Int(i)),
+            Number::Float(f) if f.is_finite() && f.trunc().abs() < 9.2e18 => {
+                Some(Var::Int(f.trunc() as i64))
+            }
+            Number::Float(_) => None,
+        },
+        ("float", Var::String(s)) => s.trim().parse().ok().map(Var::Float),
+        ("float", value) => to_float(number(value)?).map(Var::Float),
+        ("str", Var::String(s)) => Some(Var::String(s)),
+        ("str", Var::Int(i)) => Some(Var::String(i.to_string())),
+        ("str", Var::Bool(b)) => Some(Var::String(if b { "True" } else { "False" }.to_string())),
+        // Python's float formatting differs from Rust's
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::evaluate;
+    use crate::graph::Var;
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(evaluate("2 + 3 * 4"), Some(Var::Int(14)));
+        assert_eq!(evaluate("(-7) // 2"), Some(Var::Int(-4)));
+        assert_eq!(evaluate("-7 % 3"), Some(Var::Int(2)));
+        assert_eq!(evaluate("-2 ** 2"), Some(Var::Int(-4)));
+        assert_eq!(evaluate("2 ** -1"), Some(Var::Float(0.5)));
+        assert_eq!(evaluate("7 / 2"), Some(Var::Float(3.5)));
+        assert_eq!(evaluate("-7.5 // 2.0"), Some(Var::Float(-4.0)));
+        assert_eq!(evaluate("float(1) + True"), Some(Var::Float(2.0)));
+        assert_eq!(
+            evaluate("\"a\\\"b\" + str(1)"),
+            Some(Var::String("a\"b1".to_string()))
+        );
+        assert_eq!(evaluate("1 < 2 < 2"), Some(Var::Bool(false)));
+        assert_eq!(evaluate("not 0"), Some(Var::Bool(true)));
+        assert_eq!(evaluate("int(-2.7)"), Some(Var::Int(-2)));
+
+        // Python raises, or the value isn't known
+        assert_eq!(evaluate("1 / 0"), None);
+        assert_eq!(evaluate("9223372036854775807 + 1"), None);
+        assert_eq!(evaluate("(-8.0) ** 0.5"), None);
+        assert_eq!(evaluate("\"a\" < 1"), None);
+        assert_eq!(evaluate("output_1_0 + 1"), None);
+        assert_eq!(evaluate("str(1.5)"), None);
+    }
+}
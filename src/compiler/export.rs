@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::{Compilation, Ir};
+use crate::graph::Var;
+
+const SCRIPT: &str = "main.py";
+const WORKSPACE: &str = "workspace.no3";
+
+/// What a compiled program needs and exposes, written next to it on export.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub requirements: Vec<String>,
+    pub arguments: Vec<(String, Var)>, // Command line options with their defaults
+    pub outputs: Vec<String>,          // Keys of the JSON printed on exit
+}
+
+impl Manifest {
+    pub fn new(ir: &Ir) -> Self {
+        Self {
+            requirements: ir.requirements.clone(),
+            arguments: ir.arguments.clone(),
+            outputs: ir.graph_outputs.clone(),
+        }
+    }
+}
+
+/// Writes `compilation` to `dir` as a project that runs on its own: the
+/// script, its requirements, a README on how to run it and, if given, the
+/// workspace it was compiled from.
+pub fn export(dir: &Path, compilation: &Compilation, workspace: Option<String>) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let name = dir
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "graph".to_string());
+
+    let files = [
+        (SCRIPT, Some(compilation.code.clone())),
+        (
+            "requirements.txt",
+            Some(requirements(&compilation.manifest)),
+        ),
+        (
+            "README.md",
+            Some(readme(&name, &compilation.manifest, workspace.is_some())),
+        ),
+        (WORKSPACE, workspace),
+    ];
+    for (file, contents) in files {
+        let Some(contents) = contents else {
+            continue;
+        };
+        std::fs::write(dir.join(file), contents)
+            .with_context(|| format!("Failed to write {}", file))?;
+    }
+    Ok(())
+}
+
+fn requirements(manifest: &Manifest) -> String {
+    let mut output = String::new();
+    for requirement in &manifest.requirements {
+        output.push_str(requirement);
+        output.push('\n');
+    }
+    output
+}
+
+fn readme(name: &str, manifest: &Manifest, with_workspace: bool) -> String {
+    let mut output = format!(
+        "# {}\n\nGenerated by NodesO₃ v{} from a node graph.\n\n## Running\n\n```sh\n",
+        name,
+        env!("CARGO_PKG_VERSION")
+    );
+    if !manifest.requirements.is_empty() {
+        output.push_str("pip install -r requirements.txt\n");
+    }
+    output.push_str(&format!("python3 {}\n```\n", SCRIPT));
+
+    if !manifest.arguments.is_empty() {
+        output.push_str("\n## Options\n\n| Option | Type | Default |\n| --- | --- | --- |\n");
+        for (name, default) in &manifest.arguments {
+            output.push_str(&format!(
+                "| `--{}` | {} | `{}` |\n",
+                name,
+                default.type_(),
+                match default {
+                    Var::Float(f) => format!("{:?}", f),
+                    default => default.to_string(),
+                }
+            ));
+        }
+    }
+    if !manifest.outputs.is_empty() {
+        output.push_str(
+            "\n## Outputs\n\nOn exit, the program prints a JSON object with the keys:\n\n",
+        );
+        for name in &manifest.outputs {
+            output.push_str(&format!("- `{}`\n", name));
+        }
+    }
+    if with_workspace {
+        output.push_str(&format!(
+            "\n## Source\n\n`{}` is the workspace the script was compiled from. Open it in NodesO₃ to edit the graph.\n",
+            WORKSPACE
+        ));
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::{readme, Manifest};
+    use crate::graph::Var;
+
+    #[test]
+    fn test_readme() {
+        let manifest = Manifest {
+            requirements: vec!["numpy>=1.26".to_string()],
+            arguments: vec![("scale".to_string(), Var::Float(2.0))],
+            outputs: vec!["result".to_string()],
+        };
+        let readme = readme("scaler", &manifest, false);
+        assert!(readme.starts_with("# scaler\n"));
+        assert!(readme.contains("pip install -r requirements.txt\npython3 main.py\n"));
+        assert!(readme.contains("| `--scale` | Float | `2.0` |\n"));
+        assert!(readme.contains("- `result`\n"));
+        assert!(!readme.contains("## Source"));
+    }
+}
@@ -17,4 +17,16 @@ impl IOMap {
     pub fn get(&self, from: (usize, usize)) -> Option<&(usize, usize)> {
         self.io.get(&from)
     }
+
+    /// Whether anything is connected to the output `output`.
+    pub fn is_used(&self, output: (usize, usize)) -> bool {
+        self.io.values().any(|from| *from == output)
+    }
+
+    /// Every connection as `(to, from)`, sorted by the input.
+    pub fn connections(&self) -> Vec<((usize, usize), (usize, usize))> {
+        let mut connections: Vec<_> = self.io.iter().map(|(to, from)| (*to, *from)).collect();
+        connections.sort();
+        connections
+    }
 }
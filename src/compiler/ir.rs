@@ -0,0 +1,164 @@
+use std::fmt;
+
+use super::TypeMap;
+use crate::graph::{Coercion, CustomType, Type, Var, Variable};
+
+/// An output port, `(node, port)`. Every value is assigned once.
+pub type ValueId = (usize, usize);
+
+/// The program between the graph and the code written by a backend: what
+/// every compiled node computes, from which values, with resolved types.
+#[derive(Debug, Clone)]
+pub struct Ir {
+    pub custom_types: Vec<CustomType>, // Used ones, after the types of their fields
+    pub arguments: Vec<(String, Var)>, // Command line arguments with their defaults
+    pub variables: Vec<Variable>,
+    pub body: Block,
+    pub graph_outputs: Vec<String>,
+    pub requirements: Vec<String>, // Python packages the implementations import
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Block {
+    pub ops: Vec<Op>,
+}
+
+/// The code of one node.
+#[derive(Debug, Clone)]
+pub struct Op {
+    pub node: usize,
+    pub title: String,
+    pub pure: bool,
+    /// Implementation with the `{f_<name>}`, `{i_<name>}`, `{o_<name>}` and
+    /// `{b_<name>}` placeholders left for the backend.
+    pub code: String,
+    pub inputs: Vec<Input>,
+    pub fields: Vec<(String, Var)>,
+    pub outputs: Vec<Output>,
+    pub blocks: Vec<(String, Block)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Input {
+    pub name: String,
+    pub operand: Operand,
+    pub coercion: Option<Coercion>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub name: String,
+    pub value: ValueId,
+    pub data_type: Type,
+}
+
+#[derive(Debug, Clone)]
+pub enum Operand {
+    Value(ValueId),
+    Const(Var),
+    Inline(Box<Op>), // Pure op whose only output is used only here
+}
+
+impl Ir {
+    /// Resolved type of every value, including those of inlined ops.
+    pub fn types(&self) -> TypeMap<ValueId> {
+        fn collect(op: &Op, types: &mut TypeMap<ValueId>) {
+            for output in &op.outputs {
+                types.set(output.value, output.data_type.clone());
+            }
+            for input in &op.inputs {
+                if let Operand::Inline(op) = &input.operand {
+                    collect(op, types);
+                }
+            }
+            for (_, block) in &op.blocks {
+                block.ops.iter().for_each(|op| collect(op, types));
+            }
+        }
+        let mut types = TypeMap::new();
+        self.body.ops.iter().for_each(|op| collect(op, &mut types));
+        types
+    }
+}
+
+impl fmt::Display for Ir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for custom_type in &self.custom_types {
+            let fields: Vec<String> = custom_type
+                .fields
+                .iter()
+                .map(|field| format!("{}: {}", field.name, field.data_type))
+                .collect();
+            writeln!(f, "type {} {{ {} }}", custom_type.name, fields.join(", "))?;
+        }
+        for (name, default) in &self.arguments {
+            writeln!(f, "argument {}: {} = {}", name, default.type_(), default)?;
+        }
+        for variable in &self.variables {
+            writeln!(
+                f,
+                "variable {}: {} = {}",
+                variable.name,
+                variable.data_type(),
+                variable.value
+            )?;
+        }
+        for name in &self.graph_outputs {
+            writeln!(f, "output {}", name)?;
+        }
+        for requirement in &self.requirements {
+            writeln!(f, "require {}", requirement)?;
+        }
+        write_block(f, &self.body, 0)
+    }
+}
+
+fn write_block(f: &mut fmt::Formatter<'_>, block: &Block, depth: usize) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+    for op in &block.ops {
+        let outputs: Vec<String> = op
+            .outputs
+            .iter()
+            .map(|o| format!("{}: {}", value(o.value), o.data_type))
+            .collect();
+        if outputs.is_empty() {
+            writeln!(f, "{}{}", indent, call(op))?;
+        } else {
+            writeln!(f, "{}{} = {}", indent, outputs.join(", "), call(op))?;
+        }
+        for (name, block) in &op.blocks {
+            writeln!(f, "{}  {}:", indent, name)?;
+            write_block(f, block, depth + 1)?;
+        }
+    }
+    Ok(())
+}
+
+/// `Title#id(input: operand, ..., field = value, ...)`
+fn call(op: &Op) -> String {
+    let mut arguments: Vec<String> = op
+        .inputs
+        .iter()
+        .map(|input| {
+            let operand = match &input.operand {
+                Operand::Value(v) => value(*v),
+                Operand::Const(var) => var.to_string(),
+                Operand::Inline(op) => call(op),
+            };
+            match input.coercion {
+                Some(coercion) => format!("{}: {} as {}", input.name, operand, coercion.target()),
+                None => format!("{}: {}", input.name, operand),
+            }
+        })
+        .collect();
+    arguments.extend(
+        op.fields
+            .iter()
+            .map(|(name, var)| format!("{} = {}", name, var)),
+    );
+    format!("{}#{}({})", op.title, op.node, arguments.join(", "))
+}
+
+fn value(value: ValueId) -> String {
+    format!("%{}_{}", value.0, value.1)
+}
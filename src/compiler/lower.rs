@@ -0,0 +1,674 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use tracing::{debug, error, info, warn};
+
+use super::cache::NodeCache;
+use super::ir::{Block, Input, Ir, Op, Operand, Output};
+use super::{blocks, Blocks, IOMap, NodeMap, Scope, TypeMap, UpstreamTraversal};
+use crate::graph::{
+    custom, generics, variable, Coercion, CustomType, FieldKind, Node, NodeRole, Type, Var,
+    Variable,
+};
+
+/// Declarations from the workspace and its libraries that nodes can refer to.
+pub struct Declarations<'a> {
+    pub custom_types: &'a [CustomType],
+    pub variables: &'a [Variable],
+}
+
+/// Lowers the nodes in `traversal` to the intermediate representation, using
+/// their implementations in `lang`. Nodes whose ops are in `cache` and still
+/// valid are not lowered again.
+pub fn lower(
+    lang: &str,
+    node_map: NodeMap,
+    io_map: IOMap,
+    traversal: UpstreamTraversal,
+    decls: &Declarations,
+    cache: &mut NodeCache,
+    warnings: &mut Vec<String>,
+) -> Result<Ir> {
+    let exec_order = traversal.execution_order();
+
+    let custom_types = used_custom_types(exec_order, &node_map, decls)?;
+    let graph_inputs = graph_interface(exec_order, &node_map, NodeRole::GraphInput)?;
+    let graph_outputs = graph_interface(exec_order, &node_map, NodeRole::GraphOutput)?;
+    let arguments = arguments(decls.variables, &graph_inputs)?;
+
+    let blocks = Blocks::new(exec_order, &node_map, &io_map).context("Invalid control flow")?;
+    let context = LowerContext {
+        lang,
+        node_map: &node_map,
+        io_map: &io_map,
+        blocks: &blocks,
+        decls,
+        decls_key: decls_key(decls),
+    };
+    // Resolved types of the outputs lowered so far, keyed by (node, port)
+    let mut output_types: TypeMap<(usize, usize)> = TypeMap::new();
+    let hits = cache.hits();
+    let body = lower_scope(&context, &Scope::Root, &mut output_types, cache, warnings)?;
+    info!(
+        reused = cache.hits() - hits,
+        nodes = exec_order.len(),
+        "Lowered nodes"
+    );
+    cache.retain(exec_order);
+
+    Ok(Ir {
+        custom_types,
+        arguments,
+        variables: decls.variables.to_vec(),
+        body,
+        graph_outputs: graph_outputs.into_iter().map(|(name, _)| name).collect(),
+        requirements: requirements(lang, exec_order, &node_map),
+    })
+}
+
+struct LowerContext<'a> {
+    lang: &'a str,
+    node_map: &'a NodeMap,
+    io_map: &'a IOMap,
+    blocks: &'a Blocks,
+    decls: &'a Declarations<'a>,
+    decls_key: u64,
+}
+
+/// Lowers the nodes placed in `scope`, with the nodes placed in the blocks of
+/// branching nodes nested in them.
+fn lower_scope(
+    context: &LowerContext,
+    scope: &Scope,
+    output_types: &mut TypeMap<(usize, usize)>,
+    cache: &mut NodeCache,
+    warnings: &mut Vec<String>,
+) -> Result<Block> {
+    let mut block = Block::default();
+    for &node_id in context.blocks.children(scope) {
+        let Some(node) = context.node_map.get(node_id) else {
+            bail!("Node ID {} not found in NodeMap", node_id);
+        };
+        let names: Vec<String> = node
+            .impl_for_lang(context.lang)
+            .map(|i| blocks::placeholders(&i.code))
+            .unwrap_or_default()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        for name in &names {
+            let is_flow = node
+                .desc
+                .outputs
+                .iter()
+                .any(|o| &o.name == name && o.data_type == Type::Flow);
+            if !is_flow && !blocks::is_lazy_input(node, name) {
+                bail!(
+                    "Block {} of {}#{} is neither a flow output nor an input",
+                    name,
+                    node.desc.title,
+                    node.id
+                );
+            }
+        }
+
+        // Lazy inputs are computed before the node reads them, flow outputs
+        // run after it, so they can use its other outputs.
+        let mut nested = Vec::new();
+        for name in names.iter().filter(|n| blocks::is_lazy_input(node, n)) {
+            let scope = Scope::Block(node_id, name.clone());
+            nested.push((
+                name.clone(),
+                lower_scope(context, &scope, output_types, cache, warnings)?,
+            ));
+        }
+        let key = cache_key(context, node, output_types);
+        let mut op = if let Some((op, cached_warnings)) = cache.get(node_id, key) {
+            warnings.extend_from_slice(cached_warnings);
+            for output in &op.outputs {
+                output_types.set(output.value, output.data_type.clone());
+            }
+            op.clone()
+        } else {
+            let mut node_warnings = Vec::new();
+            let op = lower_node(
+                context.lang,
+                node,
+                context.node_map,
+                context.io_map,
+                output_types,
+                context.decls,
+                &mut node_warnings,
+            )
+            .context(format!("Failed to lower node {}", node.id))?;
+            warnings.extend_from_slice(&node_warnings);
+            cache.insert(node_id, key, op.clone(), node_warnings);
+            op
+        };
+        for name in names.iter().filter(|n| !blocks::is_lazy_input(node, n)) {
+            let scope = Scope::Block(node_id, name.clone());
+            nested.push((
+                name.clone(),
+                lower_scope(context, &scope, output_types, cache, warnings)?,
+            ));
+        }
+        op.blocks = nested;
+        block.ops.push(op);
+    }
+    Ok(block)
+}
+
+/// Hashes everything lowering `node` depends on: its descriptor with the field
+/// values, the outputs connected to it and their types. Flow inputs only
+/// place the node in a block.
+fn cache_key(context: &LowerContext, node: &Node, output_types: &TypeMap<(usize, usize)>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    context.lang.hash(&mut hasher);
+    context.decls_key.hash(&mut hasher);
+    node.desc_hash().hash(&mut hasher);
+    for field in &node.desc.fields {
+        ron::to_string(&field.value)
+            .unwrap_or_default()
+            .hash(&mut hasher);
+    }
+    for (i, input) in node.desc.inputs.iter().enumerate() {
+        if input.data_type == Type::Flow {
+            continue;
+        }
+        let Some(&from) = context.io_map.get((node.id, i)) else {
+            continue;
+        };
+        (i, from).hash(&mut hasher);
+        let declared = context
+            .node_map
+            .get(from.0)
+            .and_then(|n| n.desc.outputs.get(from.1))
+            .map(|o| &o.data_type);
+        format!("{:?} {:?}", output_types.get(from), declared).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Hash of the custom types and variables, which any node may refer to.
+fn decls_key(decls: &Declarations) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    ron::to_string(decls.custom_types)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    ron::to_string(decls.variables)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Names and `default` field values of the graph input or output nodes in
+/// `exec_order`. Names must be unique identifiers.
+fn graph_interface(
+    exec_order: &[usize],
+    node_map: &NodeMap,
+    role: NodeRole,
+) -> Result<Vec<(String, Option<Var>)>> {
+    let mut interface: Vec<(String, Option<Var>)> = Vec::new();
+    for node in exec_order.iter().filter_map(|id| node_map.get(*id)) {
+        if node.desc.role != role {
+            continue;
+        }
+        let field = |name: &str| {
+            node.desc
+                .fields
+                .iter()
+                .find(|f| f.name == name)
+                .map(|f| f.value.clone())
+        };
+        let Some(Var::String(name)) = field("name") else {
+            bail!("{}#{} has no name", node.desc.title, node.id);
+        };
+        if !variable::is_valid_name(&name) {
+            bail!(
+                "{}#{}: '{}' is not a valid name",
+                node.desc.title,
+                node.id,
+                name
+            );
+        }
+        if interface.iter().any(|(n, _)| n == &name) {
+            bail!("Graph {:?} '{}' is declared twice", role, name);
+        }
+        interface.push((name, field("default")));
+    }
+    Ok(interface)
+}
+
+fn lower_node(
+    lang: &str,
+    node: &crate::graph::Node,
+    node_map: &NodeMap,
+    io_map: &IOMap,
+    output_types: &mut TypeMap<(usize, usize)>,
+    decls: &Declarations,
+    warnings: &mut Vec<String>,
+) -> Result<Op> {
+    let custom_types = decls.custom_types;
+    let mut inputs = Vec::new();
+    let mut fields = Vec::new();
+    let mut input_type_map: TypeMap<String> = TypeMap::new();
+
+    let impl_desc = node.impl_for_lang(lang).ok_or_else(|| {
+        anyhow!(
+            "No {} implementation found for node {}#{}",
+            lang,
+            node.desc.title,
+            node.id
+        )
+    })?;
+    let mut code = format!(
+        "{}{}",
+        impl_desc.type_check.clone().unwrap_or(String::new()),
+        impl_desc.code
+    );
+    replace(
+        &mut code,
+        "{title}".to_string(),
+        node.desc.title.to_string(),
+    );
+    replace(&mut code, "{id}".to_string(), node.id.to_string());
+
+    let mut connected_types = Vec::new();
+    for (i, input) in node.desc.inputs.iter().enumerate() {
+        if input.data_type == Type::Flow {
+            continue;
+        }
+        if let Some(&(from_node, from_port)) = io_map.get((node.id, i)) {
+            let from_data_type = if let Some(t) = output_types.get((from_node, from_port)) {
+                t.clone()
+            } else if let Some(from_node) = node_map.get(from_node) {
+                if let Some(from_port) = from_node.desc.outputs.get(from_port) {
+                    from_port.data_type.clone()
+                } else {
+                    bail!(
+                        "Output port {} of node {} not found",
+                        from_port,
+                        from_node.id
+                    );
+                }
+            } else {
+                bail!("Node ID {} not found in NodeMap", from_node);
+            };
+            connected_types.push((i, from_data_type));
+        }
+    }
+    let bindings = generics::bind(&node.desc, connected_types.clone(), custom_types)
+        .context(format!("Type error in {}#{}", node.desc.title, node.id))?;
+
+    for (i, input) in node.desc.inputs.iter().enumerate() {
+        // Flow inputs only decide which block the node is written in
+        if input.data_type == Type::Flow {
+            continue;
+        }
+        if let Some(&(from_node, from_port)) = io_map.get((node.id, i)) {
+            let from_data_type = connected_types
+                .iter()
+                .find(|(index, _)| *index == i)
+                .map(|(_, t)| t.clone())
+                .unwrap();
+            let expected = match variable_inherit(&input.data_type) {
+                Some(field_name) => field_variable(node, field_name, decls.variables)?.data_type(),
+                None => generics::resolve(&input.data_type, &node.desc.generics, &bindings),
+            };
+            let mut coercion = None;
+            if !custom::compatible(&expected, &from_data_type, custom_types) {
+                let Some(found) = Coercion::find(&from_data_type, &expected) else {
+                    bail!(
+                        "Input {} of node {} expects {} but is connected to {}",
+                        input.name,
+                        node.id,
+                        expected,
+                        from_data_type
+                    );
+                };
+                if found.is_lossy() {
+                    let warning = format!(
+                        "{}#{}: input {} converts {} to {}, which may lose precision",
+                        node.desc.title,
+                        node.id,
+                        input.name,
+                        from_data_type,
+                        found.target()
+                    );
+                    warn!("{}", warning);
+                    warnings.push(warning);
+                }
+                coercion = Some(found);
+            }
+            let mut data_type = input.data_type.clone();
+
+            match &data_type {
+                Type::Inherit(inherited) => {
+                    let inherited_type = if inherited.starts_with("ti_") {
+                        let inherited_name = inherited.trim_start_matches("ti_");
+                        input_type_map.print();
+                        debug!(type_name = ?inherited_name, "Looking for type in input_type_map");
+                        if let Some(t) = input_type_map.get(inherited_name.to_string()) {
+                            Some(t.clone())
+                        } else {
+                            error!(
+                                ?inherited,
+                                "Inherited type is from an input but not found in InputTypeMap"
+                            );
+                            None
+                        }
+                    } else if inherited.starts_with("va_") {
+                        Some(expected.clone())
+                    } else {
+                        error!(?inherited, "Inherited type is not from an input or field");
+                        None
+                    };
+
+                    if let Some(inherited_type) = &inherited_type {
+                        input_type_map.set(input.name.clone(), inherited_type.clone());
+                        data_type = inherited_type.clone();
+                    } else {
+                        error!("Type for inherited input was not found");
+                    }
+                }
+                Type::Generic(_) => {
+                    data_type = generics::resolve(&data_type, &node.desc.generics, &bindings);
+                    input_type_map.set(input.name.clone(), data_type.clone());
+                }
+                _ => {
+                    input_type_map.set(
+                        input.name.clone(),
+                        coercion.map_or(from_data_type.clone(), |c| c.target()),
+                    );
+                }
+            }
+
+            replace(
+                &mut code,
+                format!("{{ti_{}}}", input.name),
+                data_type.to_string(),
+            );
+
+            inputs.push(Input {
+                name: input.name.clone(),
+                operand: Operand::Value((from_node, from_port)),
+                coercion,
+            });
+            continue;
+        } else {
+            bail!("Input {} of node {} is not connected", input.name, node.id);
+        }
+    }
+
+    for field in &node.desc.fields {
+        if let Some(error) = field.error() {
+            bail!(
+                "Field {} of {}#{} is invalid: {}",
+                field.name,
+                node.desc.title,
+                node.id,
+                error
+            );
+        }
+        fields.push((field.name.clone(), field.value.clone()));
+        if let FieldKind::Variable = field.kind {
+            let variable = field_variable(node, &field.name, decls.variables)?;
+            replace(&mut code, format!("{{v_{}}}", field.name), variable.ident());
+        }
+    }
+
+    let mut outputs = Vec::new();
+    for (i, output) in node.desc.outputs.iter().enumerate() {
+        let output_type = match &output.data_type {
+            Type::Inherit(inherited) => {
+                if let Some(input_name) = inherited.strip_prefix("ti_") {
+                    input_type_map.get(input_name.to_string()).cloned()
+                } else if let Some(field_name) = inherited.strip_prefix("fi_") {
+                    node.desc
+                        .fields
+                        .iter()
+                        .find(|f| f.name == field_name)
+                        .map(|f| f.value.type_())
+                } else if let Some(field_name) = inherited.strip_prefix("va_") {
+                    Some(field_variable(node, field_name, decls.variables)?.data_type())
+                } else if let Some(input_name) = inherited.strip_prefix("el_") {
+                    match input_type_map.get(input_name.to_string()) {
+                        Some(Type::List(element)) => Some((**element).clone()),
+                        Some(other) => bail!(
+                            "Input {} of {}#{} must be a List, got {}",
+                            input_name,
+                            node.desc.title,
+                            node.id,
+                            other
+                        ),
+                        None => None,
+                    }
+                } else {
+                    None
+                }
+            }
+            data_type => Some(generics::resolve(data_type, &node.desc.generics, &bindings)),
+        };
+        if let Some(output_type) = &output_type {
+            output_types.set((node.id, i), output_type.clone());
+        }
+        // Flow outputs name blocks rather than values
+        if output.data_type != Type::Flow {
+            outputs.push(Output {
+                name: output.name.clone(),
+                value: (node.id, i),
+                data_type: output_type.unwrap_or(output.data_type.clone()),
+            });
+        }
+    }
+
+    Ok(Op {
+        node: node.id,
+        title: node.desc.title.clone(),
+        pure: node.desc.pure,
+        code,
+        inputs,
+        fields,
+        outputs,
+        blocks: Vec::new(),
+    })
+}
+
+/// The field named by an `Inherit(va_<field>)` type, whose value names a workspace variable.
+fn variable_inherit(data_type: &Type) -> Option<&str> {
+    match data_type {
+        Type::Inherit(inherited) => inherited.strip_prefix("va_"),
+        _ => None,
+    }
+}
+
+/// The workspace variable named by the field `field_name` of `node`.
+fn field_variable<'a>(
+    node: &Node,
+    field_name: &str,
+    variables: &'a [Variable],
+) -> Result<&'a Variable> {
+    let field = node
+        .desc
+        .fields
+        .iter()
+        .find(|f| f.name == field_name)
+        .ok_or_else(|| anyhow!("Field {} of node {} not found", field_name, node.id))?;
+    let Var::String(name) = &field.value else {
+        bail!(
+            "Field {} of node {} does not name a variable",
+            field_name,
+            node.id
+        );
+    };
+    variable::find(variables, name).ok_or_else(|| {
+        anyhow!(
+            "{}#{} uses variable '{}', which is not defined",
+            node.desc.title,
+            node.id,
+            name
+        )
+    })
+}
+
+/// Command line arguments of the program with their defaults: the parameters
+/// and the graph inputs.
+fn arguments(
+    variables: &[Variable],
+    graph_inputs: &[(String, Option<Var>)],
+) -> Result<Vec<(String, Var)>> {
+    for variable in variables {
+        if !variable::is_valid_name(&variable.name) {
+            bail!("'{}' is not a valid variable name", variable.name);
+        }
+    }
+    let mut arguments: Vec<(String, Var)> = Vec::new();
+    for parameter in variables.iter().filter(|v| v.parameter) {
+        arguments.push((parameter.name.clone(), parameter.value.clone()));
+    }
+    for (name, default) in graph_inputs {
+        let Some(default) = default else {
+            bail!("Graph input '{}' has no default value", name);
+        };
+        if arguments.iter().any(|(n, _)| n == name) {
+            bail!("Graph input '{}' has the same name as a parameter", name);
+        }
+        arguments.push((name.clone(), default.clone()));
+    }
+    for (name, default) in &arguments {
+        if !matches!(
+            default,
+            Var::Bool(_) | Var::Int(_) | Var::Float(_) | Var::String(_)
+        ) {
+            bail!("Argument {} must be a Bool, Int, Float or String", name);
+        }
+    }
+    Ok(arguments)
+}
+
+/// The Python packages required by the implementations in `lang` of the nodes
+/// in `exec_order`, sorted and without duplicates.
+fn requirements(lang: &str, exec_order: &[usize], node_map: &NodeMap) -> Vec<String> {
+    let mut requirements: Vec<String> = exec_order
+        .iter()
+        .filter_map(|id| node_map.get(*id)?.impl_for_lang(lang)?.required.clone())
+        .flatten()
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty())
+        .collect();
+    requirements.sort();
+    requirements.dedup();
+    requirements
+}
+
+/// The custom types used by the nodes in `exec_order` or the workspace
+/// variables, including custom types nested in their fields. Types come after
+/// the types of their fields.
+fn used_custom_types(
+    exec_order: &[usize],
+    node_map: &NodeMap,
+    decls: &Declarations,
+) -> Result<Vec<CustomType>> {
+    let custom_types = decls.custom_types;
+    let mut used: Vec<String> = Vec::new();
+    let mut pending: Vec<Type> = decls.variables.iter().map(|v| v.data_type()).collect();
+    for node_id in exec_order {
+        if let Some(node) = node_map.get(*node_id) {
+            pending.extend(node.desc.inputs.iter().map(|p| p.data_type.clone()));
+            pending.extend(node.desc.outputs.iter().map(|p| p.data_type.clone()));
+            pending.extend(node.desc.fields.iter().map(|f| f.data_type.clone()));
+        }
+    }
+    while let Some(data_type) = pending.pop() {
+        match data_type {
+            Type::Custom(name) if !used.contains(&name) => {
+                let custom_type = custom::find(custom_types, &name)
+                    .ok_or_else(|| anyhow!("Custom type '{}' is not declared", name))?;
+                pending.extend(custom_type.fields.iter().map(|f| f.data_type.clone()));
+                used.push(name);
+            }
+            Type::List(t) | Type::Optional(t) => pending.push(*t),
+            Type::Map(k, v) => {
+                if !k.is_hashable() {
+                    bail!("Map key type {} cannot be used as a dictionary key", k);
+                }
+                pending.push(*k);
+                pending.push(*v);
+            }
+            Type::Multi(types) => pending.extend(types),
+            _ => {}
+        }
+    }
+    Ok(used
+        .iter()
+        .rev()
+        .map(|name| custom::find(custom_types, name).unwrap().clone())
+        .collect())
+}
+
+fn replace(s: &mut String, from: String, to: String) {
+    *s = s.replace(&from, &to);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lower, Declarations};
+    use crate::compiler::{IOMap, NodeCache, NodeMap, UpstreamTraversal};
+    use crate::graph::{Connection, DescStorage, Node, Var};
+
+    #[test]
+    fn test_lower() {
+        let mut storage = DescStorage::new();
+        storage.import_std_libs().unwrap();
+        let mut nodes = vec![
+            Node::new(&storage, 0, "Math", "Const").unwrap(),
+            Node::new(&storage, 1, "Math", "Const").unwrap(),
+            Node::new(&storage, 2, "Math", "Add").unwrap(),
+            Node::new(&storage, 3, "Debug", "Print").unwrap(),
+        ];
+        nodes[1].desc.fields[0].value = Var::Float(0.5);
+        let conn = |from, to| Connection { from, to };
+        let conns = vec![
+            conn((0, 0), (2, 0)),
+            conn((1, 0), (2, 1)),
+            conn((2, 0), (3, 0)),
+        ];
+        let decls = Declarations {
+            custom_types: &storage.custom_types(),
+            variables: &[],
+        };
+        let mut cache = NodeCache::default();
+        let compile = |nodes: &Vec<Node>, cache: &mut NodeCache| {
+            let node_map = NodeMap::new(nodes);
+            let io_map = IOMap::new(&conns);
+            let mut traversal = UpstreamTraversal::new();
+            traversal.traverse(3, &node_map, &io_map);
+            let ir = lower(
+                "python3",
+                node_map,
+                io_map,
+                traversal,
+                &decls,
+                cache,
+                &mut Vec::new(),
+            );
+            ir.unwrap().to_string()
+        };
+        assert_eq!(
+            compile(&nodes, &mut cache),
+            "%0_0: Int = Const#0(value = 0)\n\
+             %1_0: Float = Const#1(value = 0.5)\n\
+             %2_0: Float = Add#2(A: %0_0 as Float, B: %1_0)\n\
+             Print#3(value: %2_0)\n"
+        );
+
+        // Only the changed node is lowered again
+        nodes[1].desc.fields[0].value = Var::Float(1.5);
+        assert!(compile(&nodes, &mut cache).contains("Const#1(value = 1.5)"));
+        assert_eq!(cache.hits(), 3);
+
+        // Add#2 depends on the type of Const#1
+        nodes[1].desc.fields[0].value = Var::Int(1);
+        assert!(compile(&nodes, &mut cache).contains("%2_0: Int = Add#2(A: %0_0, B: %1_0)"));
+        assert_eq!(cache.hits(), 4);
+    }
+}
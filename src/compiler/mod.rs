@@ -1,21 +1,38 @@
+mod blocks;
+mod cache;
 mod compilation;
+mod debugger;
+mod diagnostics;
+mod eval;
+mod export;
 mod iomap;
+mod ir;
+mod lower;
 mod node_map;
+mod optimize;
 mod traversal;
 mod type_map;
 mod writer;
 
+pub use blocks::{Blocks, Scope};
+pub use cache::NodeCache;
 pub use compilation::Compilation;
+pub use debugger::Debugger;
+pub use diagnostics::{Diagnostic, DiagnosticKind};
+pub use export::{export, Manifest};
 pub use iomap::IOMap;
+pub use ir::Ir;
+pub use lower::{lower, Declarations};
 pub use node_map::NodeMap;
+pub use optimize::optimize;
 pub use traversal::UpstreamTraversal;
 pub use type_map::TypeMap;
-pub use writer::write;
+pub use writer::{Backend, Python};
 
 use anyhow::{anyhow, Context, Result};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-use crate::graph::{Connection, Node};
+use crate::graph::{Connection, CustomType, Node, Variable};
 
 pub enum Stage {
     Raw {
@@ -31,6 +48,8 @@ pub enum Stage {
         io_map: IOMap,
         traversal: UpstreamTraversal,
     },
+    Lowered(Ir),
+    Optimized(Ir),
     Finished(String),
 }
 
@@ -40,14 +59,21 @@ impl std::fmt::Display for Stage {
             Stage::Raw { .. } => write!(f, "Raw"),
             Stage::Maps { .. } => write!(f, "Maps"),
             Stage::Traversal { .. } => write!(f, "Traversal"),
+            Stage::Lowered(_) => write!(f, "Lowered"),
+            Stage::Optimized(_) => write!(f, "Optimized"),
             Stage::Finished(_) => write!(f, "Finished"),
         }
     }
 }
 
 pub struct Compiler {
-    final_node: usize,
+    final_nodes: Vec<usize>,
     debug_info: bool,
+    optimize: bool,
+    backend: Box<dyn Backend>,
+    cache: NodeCache,
+    custom_types: Vec<CustomType>,
+    variables: Vec<Variable>,
     stage: Stage,
     compilation: Compilation,
 }
@@ -55,22 +81,48 @@ pub struct Compiler {
 impl Compiler {
     pub fn new(
         debug_info: bool,
+        optimize: bool,
         nodes: Vec<Node>,
         conns: Vec<Connection>,
-        final_node: usize,
+        custom_types: Vec<CustomType>,
+        variables: Vec<Variable>,
+        final_nodes: Vec<usize>,
     ) -> Self {
-        let stage = Stage::Raw {
-            nodes: nodes.clone(),
-            conns: conns.clone(),
-        };
+        let stage = Stage::Raw { nodes, conns };
         Self {
-            final_node,
+            final_nodes,
             debug_info,
+            optimize,
+            backend: Box::new(Python),
+            cache: NodeCache::default(),
+            custom_types,
+            variables,
             stage,
             compilation: Compilation::new(),
         }
     }
 
+    /// Reuses the ops lowered by an earlier compilation.
+    pub fn with_cache(mut self, cache: NodeCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// What the last step produced.
+    pub fn stage(&self) -> &Stage {
+        &self.stage
+    }
+
+    /// Warnings and diagnostics collected by the steps so far.
+    pub fn compilation(&self) -> &Compilation {
+        &self.compilation
+    }
+
+    /// The ops lowered so far, to pass to the next compilation.
+    pub fn into_cache(self) -> NodeCache {
+        self.cache
+    }
+
     pub fn step(&mut self) -> Result<()> {
         let result = match &self.stage {
             Stage::Raw { nodes, conns } => {
@@ -80,8 +132,24 @@ impl Compiler {
                 Ok(Stage::Maps { node_map, io_map })
             }
             Stage::Maps { node_map, io_map } => {
+                let (final_nodes, ignored) =
+                    diagnostics::end_nodes(&self.final_nodes, node_map, io_map);
                 let mut traversal = UpstreamTraversal::new();
-                traversal.traverse(self.final_node, node_map, io_map);
+                for final_node in &final_nodes {
+                    traversal.traverse(*final_node, node_map, io_map);
+                }
+                let ignored_ids: Vec<usize> = ignored.iter().map(|d| d.node).collect();
+                let unused = diagnostics::unused(
+                    node_map,
+                    io_map,
+                    traversal.execution_order(),
+                    &ignored_ids,
+                );
+                for diagnostic in ignored.iter().chain(&unused) {
+                    warn!("{}", diagnostic.message);
+                }
+                self.compilation.diagnostics.extend(ignored);
+                self.compilation.diagnostics.extend(unused);
 
                 Ok(Stage::Traversal {
                     node_map: node_map.clone(),
@@ -94,13 +162,32 @@ impl Compiler {
                 node_map,
                 io_map,
             } => {
-                let output = write(
-                    self.debug_info,
+                let ir = lower(
+                    self.backend.lang(),
                     node_map.clone(),
                     io_map.clone(),
                     traversal.clone(),
+                    &Declarations {
+                        custom_types: &self.custom_types,
+                        variables: &self.variables,
+                    },
+                    &mut self.cache,
+                    &mut self.compilation.warnings,
                 )
-                .context("Failed to write output")?;
+                .context("Failed to lower nodes")?;
+                self.compilation.manifest = Manifest::new(&ir);
+                Ok(Stage::Lowered(ir))
+            }
+            Stage::Lowered(ir) if self.optimize => {
+                let mut ir = ir.clone();
+                optimize(&mut ir);
+                Ok(Stage::Optimized(ir))
+            }
+            Stage::Lowered(ir) | Stage::Optimized(ir) => {
+                let output = self
+                    .backend
+                    .write(ir, self.debug_info)
+                    .context("Failed to write output")?;
                 Ok(Stage::Finished(output))
             }
             Stage::Finished(_) => Err(anyhow!("Already finished")),
@@ -129,6 +216,9 @@ impl Compiler {
             self.compilation
                 .add_elapsed_time(&self.stage.to_string(), duration);
 
+            if let Stage::Lowered(ir) | Stage::Optimized(ir) = &self.stage {
+                self.compilation.ir = ir.to_string();
+            }
             if let Stage::Finished(r) = &self.stage {
                 info!("Compilation finished");
                 self.compilation.set_code(r.clone());
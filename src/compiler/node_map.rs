@@ -19,4 +19,11 @@ impl NodeMap {
     pub fn get(&self, id: usize) -> Option<&Node> {
         self.node_map.get(&id)
     }
+
+    /// IDs of all nodes, in ascending order.
+    pub fn ids(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = self.node_map.keys().copied().collect();
+        ids.sort();
+        ids
+    }
 }
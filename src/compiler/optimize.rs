@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use super::eval;
+use super::ir::{Block, Ir, Op, Operand, ValueId};
+use super::writer::{expression, operand, render};
+use crate::graph::Var;
+
+/// Runs the optimization passes: pure nodes with constant inputs are
+/// evaluated at compile time, repeated pure nodes are computed once, and
+/// values used once are inlined into the node using them.
+pub fn optimize(ir: &mut Ir) {
+    let mut simplifier = Simplifier::default();
+    simplifier.block(&mut ir.body, &HashMap::new());
+
+    let mut uses = HashMap::new();
+    count_uses(&ir.body, &mut uses);
+    inline(&mut ir.body, &uses);
+}
+
+#[derive(Default)]
+struct Simplifier {
+    constants: HashMap<ValueId, Var>,
+    renamed: HashMap<ValueId, ValueId>, // Duplicate value => value computed first
+}
+
+impl Simplifier {
+    /// `available` holds the pure ops computed before the block and visible
+    /// in it, keyed by their code without outputs.
+    fn block(&mut self, block: &mut Block, available: &HashMap<String, Vec<ValueId>>) {
+        let mut available = available.clone();
+        let mut kept = Vec::new();
+        for mut op in block.ops.drain(..) {
+            // Lazy inputs are computed in the blocks of the op
+            for (_, nested) in &mut op.blocks {
+                self.block(nested, &available);
+            }
+            for input in &mut op.inputs {
+                let Operand::Value(value) = input.operand else {
+                    continue;
+                };
+                let value = self.renamed.get(&value).copied().unwrap_or(value);
+                input.operand = Operand::Value(value);
+                if let Some(constant) = self.constants.get(&value) {
+                    input.operand = Operand::Const(constant.clone());
+                    if let Some(coerced) = eval::evaluate(&operand(input)) {
+                        input.operand = Operand::Const(coerced);
+                        input.coercion = None;
+                    }
+                }
+            }
+            if !op.pure || !op.blocks.is_empty() {
+                kept.push(op);
+                continue;
+            }
+
+            if let Some(constant) = expression(&op).and_then(|e| eval::evaluate(&e)) {
+                self.constants.insert(op.outputs[0].value, constant);
+                continue;
+            }
+            let key = render(&op, false);
+            let values: Vec<ValueId> = op.outputs.iter().map(|o| o.value).collect();
+            if let Some(existing) = available.get(&key) {
+                self.renamed
+                    .extend(values.into_iter().zip(existing.iter().copied()));
+                continue;
+            }
+            available.insert(key, values);
+            kept.push(op);
+        }
+        block.ops = kept;
+    }
+}
+
+fn count_uses(block: &Block, uses: &mut HashMap<ValueId, usize>) {
+    for op in &block.ops {
+        for input in &op.inputs {
+            if let Operand::Value(value) = input.operand {
+                *uses.entry(value).or_default() += 1;
+            }
+        }
+        for (_, nested) in &op.blocks {
+            count_uses(nested, uses);
+        }
+    }
+}
+
+/// Replaces values used once by the op computing them, when it is computed
+/// in the same block by a pure op and read once by the consumer.
+fn inline(block: &mut Block, uses: &HashMap<ValueId, usize>) {
+    // Ops of the block so far, `None` once inlined
+    let mut ops: Vec<Option<Op>> = Vec::new();
+    let mut inlinable: HashMap<ValueId, usize> = HashMap::new();
+    for mut op in block.ops.drain(..) {
+        for (_, nested) in &mut op.blocks {
+            inline(nested, uses);
+        }
+        for input in &mut op.inputs {
+            let Operand::Value(value) = input.operand else {
+                continue;
+            };
+            let placeholder = format!("{{i_{}}}", input.name);
+            if uses.get(&value) != Some(&1) || op.code.matches(&placeholder).count() != 1 {
+                continue;
+            }
+            if let Some(producer) = inlinable.remove(&value) {
+                input.operand = Operand::Inline(Box::new(ops[producer].take().unwrap()));
+            }
+        }
+        if op.pure && expression(&op).is_some() {
+            inlinable.insert(op.outputs[0].value, ops.len());
+        }
+        ops.push(Some(op));
+    }
+    block.ops = ops.into_iter().flatten().collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::optimize;
+    use crate::compiler::ir::{Block, Input, Ir, Op, Operand, Output};
+    use crate::compiler::writer::{Backend, Python};
+    use crate::graph::{Coercion, Type, Var};
+
+    fn op(node: usize, code: &str, inputs: Vec<(&str, Operand)>) -> Op {
+        Op {
+            node,
+            title: "Op".to_string(),
+            pure: true,
+            code: code.to_string(),
+            inputs: inputs
+                .into_iter()
+                .map(|(name, operand)| Input {
+                    name: name.to_string(),
+                    operand,
+                    coercion: None,
+                })
+                .collect(),
+            fields: Vec::new(),
+            outputs: vec![Output {
+                name: "result".to_string(),
+                value: (node, 0),
+                data_type: Type::Int,
+            }],
+            blocks: Vec::new(),
+        }
+    }
+
+    /// The optimized code of `ops`, without the prelude.
+    fn program(ops: Vec<Op>) -> String {
+        let mut ir = Ir {
+            custom_types: Vec::new(),
+            arguments: Vec::new(),
+            variables: Vec::new(),
+            body: Block { ops },
+            graph_outputs: Vec::new(),
+            requirements: Vec::new(),
+        };
+        optimize(&mut ir);
+        let code = Python.write(&ir, true).unwrap();
+        let start = code.find("# Node").unwrap_or(code.len());
+        code[start..].replace("\n\n", "\n")
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let value = |node| Operand::Value((node, 0));
+        let mut add = op(
+            2,
+            "{o_result} = {i_A} + {i_B}",
+            vec![("A", value(0)), ("B", value(1))],
+        );
+        add.inputs[1].coercion = Some(Coercion::IntToFloat);
+        let mut print = op(3, "print({i_value})", vec![("value", value(2))]);
+        print.pure = false;
+        let code = program(vec![
+            op(0, "{o_result} = 1.5", vec![]),
+            op(1, "{o_result} = 2", vec![]),
+            add,
+            print,
+        ]);
+        assert_eq!(code, "# Node Op#3\nprint(3.5)\n");
+
+        // Values that aren't known are inlined instead
+        let mut print = op(1, "print({i_value})", vec![("value", value(0))]);
+        print.pure = false;
+        let code = program(vec![op(0, "{o_result} = abs(-3)", vec![]), print]);
+        assert_eq!(code, "# Node Op#1\nprint(abs(-3))\n");
+    }
+
+    #[test]
+    fn test_placeholder_in_string() {
+        let mut constant = op(0, "{o_result} = {f_value}", vec![]);
+        constant.fields = vec![("value".to_string(), Var::String("{f_name}".to_string()))];
+        let mut output = op(
+            1,
+            "graph_output({f_name}, {i_value})",
+            vec![("value", Operand::Value((0, 0)))],
+        );
+        output.pure = false;
+        output.fields = vec![("name".to_string(), Var::String("result".to_string()))];
+        let code = program(vec![constant, output]);
+        assert_eq!(
+            code,
+            "# Node Op#1\ngraph_output(\"result\", \"{f_name}\")\n"
+        );
+    }
+
+    #[test]
+    fn test_common_subexpressions() {
+        let value = |node| Operand::Value((node, 0));
+        let mut input = op(0, "{o_result} = input()", vec![]);
+        input.pure = false;
+        let mut print = op(
+            3,
+            "print({i_A}, {i_B})",
+            vec![("A", value(1)), ("B", value(2))],
+        );
+        print.pure = false;
+        let code = program(vec![
+            input,
+            op(1, "{o_result} = {i_A} * 2", vec![("A", value(0))]),
+            op(2, "{o_result} = {i_A} * 2", vec![("A", value(0))]),
+            print,
+        ]);
+        assert_eq!(
+            code,
+            "# Node Op#0\noutput_0_0 = input()\n# Node Op#1\noutput_1_0 = output_0_0 * 2\n\
+             # Node Op#3\nprint(output_1_0, output_1_0)\n"
+        );
+    }
+}
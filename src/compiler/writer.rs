@@ -1,164 +1,288 @@
-use anyhow::{anyhow, bail, Context, Result};
-use tracing::{debug, error};
+use anyhow::{bail, Result};
 
-use super::{IOMap, NodeMap, TypeMap, UpstreamTraversal};
-use crate::graph::Type;
+use super::blocks;
+use super::ir::{Block, Input, Ir, Op, Operand, ValueId};
+use crate::graph::{Type, Var};
 
 const SYS_MODULE: &str = include_str!("../python/sys.py");
 
-pub fn write(
-    debug_info: bool,
-    node_map: NodeMap,
-    io_map: IOMap,
-    traversal: UpstreamTraversal,
-) -> Result<String> {
-    let exec_order = traversal.execution_order();
+/// Writes the code of a program from its intermediate representation.
+pub trait Backend: Send {
+    /// Language of the node implementations the backend writes.
+    fn lang(&self) -> &'static str;
 
+    fn write(&self, ir: &Ir, debug_info: bool) -> Result<String>;
+}
+
+pub struct Python;
+
+impl Backend for Python {
+    fn lang(&self) -> &'static str {
+        "python3"
+    }
+
+    fn write(&self, ir: &Ir, debug_info: bool) -> Result<String> {
+        let mut output = String::new();
+        output.push_str(&format!(
+            "# Generated by NodesO₃ v{} (https://github.com/LeviLovie/NodesO3)\n\n",
+            env!("CARGO_PKG_VERSION")
+        ));
+
+        output.push_str(SYS_MODULE);
+        output.push('\n');
+
+        output.push_str(&write_custom_types(ir));
+        output.push_str(&write_variables(ir)?);
+        output.push_str(&write_block(&ir.body, debug_info));
+
+        if !ir.graph_outputs.is_empty() {
+            output.push_str("\nimport json\n\nprint(json.dumps(_graph_outputs, default=vars))\n");
+        }
+        Ok(output)
+    }
+}
+
+/// Name of the Python variable holding `value`.
+fn value_name(value: ValueId) -> String {
+    format!("output_{}_{}", value.0, value.1)
+}
+
+fn write_block(block: &Block, debug_info: bool) -> String {
     let mut output = String::new();
-    output.push_str(&format!(
-        "# Generated by NodesO₃ v{} (https://github.com/LeviLovie/NodesO3)\n\n",
-        env!("CARGO_PKG_VERSION")
-    ));
-
-    output.push_str(SYS_MODULE);
-    output.push('\n');
-
-    for node_id in exec_order {
-        if let Some(node) = node_map.get(*node_id) {
-            let node_output = write_node(debug_info, node, &node_map, &io_map)
-                .context(format!("Failed to write node {}", node.id))?;
-            output.push_str(&node_output)
+    for op in &block.ops {
+        if debug_info {
+            output.push_str(&format!("# Node {}#{}\n", op.title, op.node));
+        }
+        let mut code = render(op, true);
+        for (name, block) in &op.blocks {
+            code = blocks::fill(&code, name, &write_block(block, debug_info));
+        }
+        output.push_str(code.trim_end_matches('\n'));
+        output.push('\n');
+        if debug_info {
+            output.push('\n');
+        }
+    }
+    output
+}
+
+/// The code of `op` with its fields and inputs filled in, and its outputs if
+/// `outputs`. Blocks are left to the caller.
+pub fn render(op: &Op, outputs: bool) -> String {
+    fill_placeholders(&op.code, |placeholder| {
+        if let Some(name) = placeholder.strip_prefix("o_") {
+            let output = op.outputs.iter().find(|o| o.name == name);
+            output.filter(|_| outputs).map(|o| value_name(o.value))
+        } else if let Some(name) = placeholder.strip_prefix("i_") {
+            op.inputs.iter().find(|i| i.name == name).map(operand)
+        } else if let Some(name) = placeholder.strip_prefix("f_") {
+            let field = op.fields.iter().find(|(n, _)| n == name);
+            field.map(|(_, value)| literal(value))
         } else {
-            bail!("Node ID {} not found in NodeMap", node_id);
+            None
+        }
+    })
+}
+
+/// Replaces each `{placeholder}` of `template` that `value` has a value for.
+/// The template is read once, so values that look like placeholders, e.g.
+/// string literals typed by the user, are kept as they are.
+fn fill_placeholders(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let filled = rest
+            .find('}')
+            .and_then(|end| Some((end, value(&rest[1..end])?)));
+        match filled {
+            Some((end, filled)) => {
+                output.push_str(&filled);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push('{');
+                rest = &rest[1..];
+            }
         }
     }
+    output.push_str(rest);
+    output
+}
 
-    Ok(output)
+/// The expression assigned by an op whose whole code is `{o_<name>} = <expr>`.
+pub fn expression(op: &Op) -> Option<String> {
+    if !op.blocks.is_empty() || op.outputs.len() != 1 {
+        return None;
+    }
+    let code = render(op, false);
+    let expr = code
+        .trim_end()
+        .strip_prefix(&format!("{{o_{}}} = ", op.outputs[0].name))?;
+    (!expr.contains('\n')).then(|| expr.to_string())
 }
 
-fn write_node(
-    debug_info: bool,
-    node: &crate::graph::Node,
-    node_map: &NodeMap,
-    io_map: &IOMap,
-) -> Result<String> {
-    let mut output = String::new();
-    if debug_info {
-        output.push_str(&format!("# Node {}#{}\n", node.desc.title, node.id));
+/// The Python expression the node reads for `input`.
+pub fn operand(input: &Input) -> String {
+    let expr = match &input.operand {
+        Operand::Value(value) => value_name(*value),
+        Operand::Const(var) => literal(var),
+        Operand::Inline(op) => expression(op).expect("Only single expressions are inlined"),
+    };
+    match input.coercion {
+        Some(coercion) => coercion.python(&expr),
+        None if is_atomic(&expr) => expr,
+        None => format!("({})", expr),
     }
+}
 
-    let mut input_type_map: TypeMap<String> = TypeMap::new();
-
-    let impl_desc = node.impl_for_lang("python3").ok_or_else(|| {
-        anyhow!(
-            "No Python3 implementation found for node {}#{}",
-            node.desc.title,
-            node.id
-        )
-    })?;
-    let mut py_impl = format!(
-        "{}{}",
-        impl_desc.type_check.clone().unwrap_or(String::new()),
-        impl_desc.code
-    );
-    replace(
-        &mut py_impl,
-        "{title}".to_string(),
-        node.desc.title.to_string(),
-    );
-    replace(&mut py_impl, "{id}".to_string(), node.id.to_string());
-
-    for (i, input) in node.desc.inputs.iter().enumerate() {
-        if let Some(&(from_node, from_port)) = io_map.get((node.id, i)) {
-            let from_data_type = if let Some(from_node) = node_map.get(from_node) {
-                if let Some(from_port) = from_node.desc.outputs.get(from_port) {
-                    from_port.data_type.clone()
-                } else {
-                    bail!(
-                        "Output port {} of node {} not found",
-                        from_port,
-                        from_node.id
-                    );
-                }
-            } else {
-                bail!("Node ID {} not found in NodeMap", from_node);
-            };
-            let mut data_type = input.data_type.clone();
-
-            match &data_type {
-                Type::Inherit(inherited) => {
-                    let inherited_type = if inherited.starts_with("ti_") {
-                        let inherited_name = inherited.trim_start_matches("ti_");
-                        input_type_map.print();
-                        debug!(type_name = ?inherited_name, "Looking for type in input_type_map");
-                        if let Some(t) = input_type_map.get(inherited_name.to_string()) {
-                            Some(t.clone())
-                        } else {
-                            error!(
-                                ?inherited,
-                                "Inherited type is from an input but not found in InputTypeMap"
-                            );
-                            None
-                        }
-                    } else {
-                        error!(?inherited, "Inherited type is not from an input or field");
-                        None
-                    };
-
-                    if let Some(inherited_type) = &inherited_type {
-                        input_type_map.set(input.name.clone(), inherited_type.clone());
-                        data_type = inherited_type.clone();
-                    } else {
-                        error!("Type for inherited input was not found");
-                    }
-                }
-                _ => {
-                    input_type_map.set(input.name.clone(), from_data_type.clone());
-                }
+/// Whether `expr` keeps its meaning next to any operator: names, literals
+/// other than negative numbers, calls and the like.
+fn is_atomic(expr: &str) -> bool {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in expr.chars() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}') => depth -= 1,
+            (None, c) if depth == 0 && (c.is_whitespace() || "+-*/%<>=!&|^~@".contains(c)) => {
+                return false;
             }
+            _ => {}
+        }
+    }
+    true
+}
 
-            replace(
-                &mut py_impl,
-                format!("{{ti_{}}}", input.name),
-                data_type.to_string(),
-            );
-
-            replace(
-                &mut py_impl,
-                format!("{{i_{}}}", input.name),
-                format!("output_{}_{}", from_node, from_port),
-            );
-            continue;
-        } else {
-            bail!("Input {} of node {} is not connected", input.name, node.id);
+/// Declares the workspace variables. Parameters and graph inputs are read
+/// from the command line, with the stored value as default.
+fn write_variables(ir: &Ir) -> Result<String> {
+    let mut output = String::new();
+    if !ir.arguments.is_empty() {
+        output.push_str("import argparse\n\n_parser = argparse.ArgumentParser()\n");
+        for (name, default) in &ir.arguments {
+            let parse = match default {
+                Var::Bool(_) => "lambda s: s.lower() in (\"1\", \"true\", \"yes\")",
+                Var::Int(_) => "int",
+                Var::Float(_) => "float",
+                Var::String(_) => "str",
+                _ => bail!("Argument {} must be a Bool, Int, Float or String", name),
+            };
+            output.push_str(&format!(
+                "_parser.add_argument(\"--{}\", dest=\"{}\", type={}, default={})\n",
+                name,
+                name,
+                parse,
+                literal(default)
+            ));
         }
+        output.push_str("_args = _parser.parse_args()\n\n");
     }
 
-    for field in &node.desc.fields {
-        replace(
-            &mut py_impl,
-            format!("{{f_{}}}", field.name),
-            field.value.to_string(),
-        );
+    for variable in &ir.variables {
+        let value = if variable.parameter {
+            format!("_args.{}", variable.name)
+        } else {
+            literal(&variable.value)
+        };
+        output.push_str(&format!("{} = {}\n", variable.ident(), value));
+    }
+    if !ir.variables.is_empty() {
+        output.push('\n');
     }
+    Ok(output)
+}
 
-    for (i, output) in node.desc.outputs.iter().enumerate() {
-        replace(
-            &mut py_impl,
-            format!("{{o_{}}}", output.name),
-            format!("output_{}_{}", node.id, i),
-        );
+/// Declares a dataclass for every custom type the program uses.
+fn write_custom_types(ir: &Ir) -> String {
+    if ir.custom_types.is_empty() {
+        return String::new();
     }
 
-    output.push_str(&py_impl);
-    output.push_str("\n");
-    if debug_info {
-        output.push_str("\n");
+    let mut output = String::from("from dataclasses import dataclass\n\n");
+    for custom_type in &ir.custom_types {
+        output.push_str(&format!("@dataclass\nclass {}:\n", custom_type.name));
+        if custom_type.fields.is_empty() {
+            output.push_str("    pass\n");
+        }
+        for field in &custom_type.fields {
+            output.push_str(&format!(
+                "    {}: \"{}\"\n",
+                field.name,
+                python_type(&field.data_type)
+            ));
+        }
+        output.push('\n');
     }
+    output
+}
 
-    Ok(output)
+fn python_type(data_type: &Type) -> String {
+    match data_type {
+        Type::Bool => "bool".to_string(),
+        Type::Int => "int".to_string(),
+        Type::Float => "float".to_string(),
+        Type::String => "str".to_string(),
+        Type::Custom(name) => name.clone(),
+        Type::List(t) => format!("list[{}]", python_type(t)),
+        Type::Map(k, v) => format!("dict[{}, {}]", python_type(k), python_type(v)),
+        Type::Optional(t) => format!("{} | None", python_type(t)),
+        Type::Multi(_) | Type::Inherit(_) | Type::Generic(_) | Type::Flow => "object".to_string(),
+    }
 }
 
-fn replace(s: &mut String, from: String, to: String) {
-    *s = s.replace(&from, &to);
+/// Encodes a value as a Python literal.
+pub fn literal(var: &Var) -> String {
+    match var {
+        Var::Bool(b) => if *b { "True" } else { "False" }.to_string(),
+        Var::Int(i) => i.to_string(),
+        Var::Float(f) if f.is_nan() => "float(\"nan\")".to_string(),
+        Var::Float(f) if f.is_infinite() => {
+            format!("float(\"{}inf\")", if *f < 0.0 { "-" } else { "" })
+        }
+        Var::Float(f) => format!("{:?}", f),
+        Var::String(s) => {
+            let mut escaped = String::from("\"");
+            for c in s.chars() {
+                match c {
+                    '\\' => escaped.push_str("\\\\"),
+                    '"' => escaped.push_str("\\\""),
+                    '\n' => escaped.push_str("\\n"),
+                    '\r' => escaped.push_str("\\r"),
+                    '\t' => escaped.push_str("\\t"),
+                    c => escaped.push(c),
+                }
+            }
+            escaped.push('"');
+            escaped
+        }
+        Var::Custom(c) => {
+            let fields: Vec<String> = c
+                .fields
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, literal(value)))
+                .collect();
+            format!("{}({})", c.name, fields.join(", "))
+        }
+        Var::List(_, items) => {
+            let items: Vec<String> = items.iter().map(literal).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Var::Map(_, _, entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(k, v)| format!("{}: {}", literal(k), literal(v)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Var::Optional(_, Some(value)) => literal(value),
+        Var::Optional(_, None) => "None".to_string(),
+    }
 }
@@ -0,0 +1,85 @@
+/// A line of a line-by-line diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change<'a> {
+    Same(&'a str),
+    Added(&'a str),
+    Removed(&'a str),
+}
+
+/// Above this many line pairs the changed middle is shown as removed and then
+/// added, instead of finding the longest common subsequence.
+const MAX_TABLE: usize = 4_000_000;
+
+/// Diffs `old` against `new` line by line, keeping as many lines as possible.
+pub fn lines<'a>(old: &'a str, new: &'a str) -> Vec<Change<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut changes: Vec<Change> = old[..prefix].iter().map(|l| Change::Same(l)).collect();
+    if a.len().saturating_mul(b.len()) > MAX_TABLE {
+        changes.extend(a.iter().map(|l| Change::Removed(l)));
+        changes.extend(b.iter().map(|l| Change::Added(l)));
+    } else {
+        // lengths[i][j]: longest common subsequence of a[i..] and b[j..]
+        let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lengths[i][j] = if a[i] == b[j] {
+                    lengths[i + 1][j + 1] + 1
+                } else {
+                    lengths[i + 1][j].max(lengths[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                changes.push(Change::Same(a[i]));
+                i += 1;
+                j += 1;
+            } else if i < a.len() && (j == b.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+                changes.push(Change::Removed(a[i]));
+                i += 1;
+            } else {
+                changes.push(Change::Added(b[j]));
+                j += 1;
+            }
+        }
+    }
+    changes.extend(old[old.len() - suffix..].iter().map(|l| Change::Same(l)));
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lines, Change};
+
+    #[test]
+    fn test_lines() {
+        let old = "a = 1\nb = 2\nprint(a)\nprint(b)\n";
+        let new = "a = 1\nc = 3\nprint(a)\nprint(c)\n";
+        assert_eq!(
+            lines(old, new),
+            vec![
+                Change::Same("a = 1"),
+                Change::Removed("b = 2"),
+                Change::Added("c = 3"),
+                Change::Same("print(a)"),
+                Change::Removed("print(b)"),
+                Change::Added("print(c)"),
+            ]
+        );
+        assert!(lines(old, old).iter().all(|c| matches!(c, Change::Same(_))));
+    }
+}
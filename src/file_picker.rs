@@ -2,12 +2,16 @@ use eframe::egui::Context;
 use egui_file_dialog::{FileDialog, FileDialogConfig};
 use std::{path::PathBuf, sync::Arc};
 
+use crate::workspace::WorkspaceFormat;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DialogPurpose {
     OpenWorkspace,
     SaveWorkspace,
     ImportLibs,
     SavePython,
+    ExportProject,
+    PickFile,
 }
 
 #[derive(Debug)]
@@ -33,6 +37,8 @@ impl FilePicker {
                 dlg_config = dlg_config.add_save_extension("NodesO₃ Workspace", "no3");
                 dlg_config =
                     dlg_config.add_save_extension("NodesO₃ Compressed Workspace", "no3zstd");
+                dlg_config = dlg_config.add_save_extension("NodesO₃ Workspace (YAML)", "no3.yaml");
+                dlg_config = dlg_config.add_save_extension("NodesO₃ Workspace (JSON)", "no3.json");
                 dlg_config.default_save_extension = Some("no3".to_string());
                 dlg_config.title = Some("Save to a Workspace".to_string());
             }
@@ -45,12 +51,26 @@ impl FilePicker {
                 dlg_config.default_save_extension = Some("py".to_string());
                 dlg_config.title = Some("Save to a Python File".to_string());
             }
+            DialogPurpose::ExportProject => {
+                dlg_config.title = Some("Export to a Directory".to_string());
+            }
+            DialogPurpose::PickFile => {
+                dlg_config.title = Some("Pick a File".to_string());
+            }
         }
         *dlg.config_mut() = dlg_config;
 
         match purpose {
             DialogPurpose::OpenWorkspace => {
-                dlg = dlg.add_file_filter_extensions("NodesO₃ Workspaces", vec!["no3", "no3zstd"]);
+                dlg = dlg.add_file_filter(
+                    "NodesO₃ Workspaces",
+                    Arc::new(|p| {
+                        let name = p.to_string_lossy().to_lowercase();
+                        WorkspaceFormat::EXTENSIONS
+                            .iter()
+                            .any(|ext| name.ends_with(ext))
+                    }),
+                );
             }
             DialogPurpose::ImportLibs => {
                 dlg = dlg.add_file_filter(
@@ -70,6 +90,24 @@ impl FilePicker {
         }
     }
 
+    /// Only lists files with one of `extensions`, if any are given.
+    pub fn with_extensions(mut self, extensions: &[String]) -> Self {
+        if !extensions.is_empty()
+            && let Some(dlg) = self.dlg.take()
+        {
+            let extensions = extensions.to_vec();
+            let dlg = dlg.add_file_filter(
+                "Allowed Files",
+                Arc::new(move |p| {
+                    p.extension()
+                        .is_some_and(|e| extensions.iter().any(|x| e == x.as_str()))
+                }),
+            );
+            self.dlg = Some(dlg.default_file_filter("Allowed Files"));
+        }
+        self
+    }
+
     pub fn show(&mut self, ctx: &Context) {
         let mut picked_paths: Option<Vec<PathBuf>> = None;
 
@@ -82,6 +120,8 @@ impl FilePicker {
                     DialogPurpose::SaveWorkspace => dlg.save_file(),
                     DialogPurpose::ImportLibs => dlg.pick_multiple(),
                     DialogPurpose::SavePython => dlg.save_file(),
+                    DialogPurpose::ExportProject => dlg.pick_directory(),
+                    DialogPurpose::PickFile => dlg.pick_file(),
                 };
             }
 
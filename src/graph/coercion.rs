@@ -0,0 +1,105 @@
+use super::Type;
+
+/// A conversion the compiler inserts when an output is connected to an input
+/// of a different type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coercion {
+    IntToFloat,
+    BoolToInt,
+    BoolToFloat,
+    FloatToInt, // Truncates towards zero
+    ToString,   // Formats any value with `str()`
+}
+
+impl Coercion {
+    /// Finds the conversion from `from` to `to`, preferring lossless ones when
+    /// `to` allows several types.
+    pub fn find(from: &Type, to: &Type) -> Option<Self> {
+        match to {
+            Type::Multi(options) => {
+                let found: Vec<Self> = options.iter().filter_map(|t| Self::find(from, t)).collect();
+                found
+                    .iter()
+                    .find(|c| !c.is_lossy())
+                    .or(found.first())
+                    .copied()
+            }
+            Type::Optional(inner) => Self::find(from, inner),
+            _ => match (from, to) {
+                (Type::Int, Type::Float) => Some(Self::IntToFloat),
+                (Type::Bool, Type::Int) => Some(Self::BoolToInt),
+                (Type::Bool, Type::Float) => Some(Self::BoolToFloat),
+                (Type::Float, Type::Int) => Some(Self::FloatToInt),
+                (Type::Multi(_) | Type::Inherit(_) | Type::Generic(_) | Type::Flow, _) => None,
+                (from, Type::String) if from != &Type::String => Some(Self::ToString),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn is_lossy(self) -> bool {
+        matches!(self, Self::FloatToInt)
+    }
+
+    /// Lossless conversion between numeric types, used to pick a common type
+    /// for type variables, e.g. `T = Float` for `Int + Float`.
+    pub fn is_widening(self) -> bool {
+        matches!(self, Self::IntToFloat | Self::BoolToInt | Self::BoolToFloat)
+    }
+
+    pub fn target(self) -> Type {
+        match self {
+            Self::IntToFloat | Self::BoolToFloat => Type::Float,
+            Self::BoolToInt | Self::FloatToInt => Type::Int,
+            Self::ToString => Type::String,
+        }
+    }
+
+    /// Wraps the Python expression `expr` in the conversion.
+    pub fn python(self, expr: &str) -> String {
+        match self {
+            Self::IntToFloat | Self::BoolToFloat => format!("float({})", expr),
+            Self::BoolToInt | Self::FloatToInt => format!("int({})", expr),
+            Self::ToString => format!("str({})", expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Coercion;
+    use crate::graph::Type;
+
+    #[test]
+    fn test_coercion_table() {
+        assert_eq!(
+            Coercion::find(&Type::Int, &Type::Float),
+            Some(Coercion::IntToFloat)
+        );
+        assert_eq!(
+            Coercion::find(&Type::Bool, &Type::Int),
+            Some(Coercion::BoolToInt)
+        );
+        assert_eq!(
+            Coercion::find(&Type::Custom("Vec2".to_string()), &Type::String),
+            Some(Coercion::ToString)
+        );
+        assert!(Coercion::find(&Type::Float, &Type::Int).unwrap().is_lossy());
+        assert_eq!(Coercion::find(&Type::String, &Type::Int), None);
+        assert_eq!(Coercion::find(&Type::String, &Type::String), None);
+    }
+
+    #[test]
+    fn test_prefers_lossless() {
+        let numeric = Type::Multi(vec![Type::Int, Type::Float]);
+        assert_eq!(
+            Coercion::find(&Type::Bool, &numeric),
+            Some(Coercion::BoolToInt)
+        );
+        let int_or_string = Type::Multi(vec![Type::Int, Type::String]);
+        assert_eq!(
+            Coercion::find(&Type::Float, &int_or_string),
+            Some(Coercion::ToString)
+        );
+    }
+}
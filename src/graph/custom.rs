@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+
+use super::{CustomValue, Type, Var};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub data_type: Type,
+    pub default: Option<Var>,
+}
+
+/// A structured type declared by a library, e.g. `Vec2 { x: Float, y: Float }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomType {
+    pub name: String,
+    pub fields: Vec<CustomField>,
+}
+
+const MAX_DEPTH: usize = 16;
+
+pub fn find<'a>(types: &'a [CustomType], name: &str) -> Option<&'a CustomType> {
+    types.iter().find(|t| t.name == name)
+}
+
+/// Default value of `data_type`, building custom types field by field.
+pub fn default_var(data_type: &Type, types: &[CustomType]) -> Option<Var> {
+    default_var_recursive(data_type, types, 0)
+}
+
+fn default_var_recursive(data_type: &Type, types: &[CustomType], depth: usize) -> Option<Var> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    match data_type {
+        Type::Bool => Some(Var::Bool(false)),
+        Type::Int => Some(Var::Int(0)),
+        Type::Float => Some(Var::Float(0.0)),
+        Type::String => Some(Var::String(String::new())),
+        Type::Custom(name) => {
+            let custom = find(types, name)?;
+            let mut fields = Vec::new();
+            for field in &custom.fields {
+                let value = match &field.default {
+                    Some(default) => default.clone(),
+                    None => default_var_recursive(&field.data_type, types, depth + 1)?,
+                };
+                fields.push((field.name.clone(), value));
+            }
+            Some(Var::Custom(CustomValue {
+                name: name.clone(),
+                fields,
+            }))
+        }
+        Type::List(t) => Some(Var::List((**t).clone(), Vec::new())),
+        Type::Map(k, v) => Some(Var::Map((**k).clone(), (**v).clone(), Vec::new())),
+        Type::Optional(t) => Some(Var::Optional((**t).clone(), None)),
+        Type::Multi(options) => options
+            .first()
+            .and_then(|t| default_var_recursive(t, types, depth + 1)),
+        Type::Inherit(_) | Type::Generic(_) | Type::Flow => None,
+    }
+}
+
+/// Whether `value` has exactly the fields declared for its custom type, and
+/// container values have the declared element types.
+pub fn matches_shape(value: &Var, data_type: &Type, types: &[CustomType]) -> bool {
+    match (value, data_type) {
+        (Var::List(elem, items), Type::List(t)) => {
+            elem == &**t && items.iter().all(|i| matches_shape(i, t, types))
+        }
+        (Var::Map(key, val, entries), Type::Map(k, v)) => {
+            key == &**k
+                && val == &**v
+                && entries
+                    .iter()
+                    .all(|(ek, ev)| matches_shape(ek, k, types) && matches_shape(ev, v, types))
+        }
+        (Var::Optional(inner, value), Type::Optional(t)) => {
+            inner == &**t && value.as_ref().is_none_or(|v| matches_shape(v, t, types))
+        }
+        (Var::Custom(value), Type::Custom(name)) => {
+            let Some(custom) = find(types, name) else {
+                return false;
+            };
+            value.fields.len() == custom.fields.len()
+                && custom
+                    .fields
+                    .iter()
+                    .zip(&value.fields)
+                    .all(|(field, (name, var))| {
+                        &field.name == name && matches_shape(var, &field.data_type, types)
+                    })
+        }
+        _ => data_type.accepts(&value.type_()),
+    }
+}
+
+/// Like [`Type::compatible_with`], but two custom types with the same field
+/// names and field types are considered compatible even if their names differ.
+pub fn compatible(input: &Type, output: &Type, types: &[CustomType]) -> bool {
+    if input.compatible_with(output) {
+        return true;
+    }
+    match (input, output) {
+        (Type::Multi(options), _) => options.iter().any(|t| compatible(t, output, types)),
+        (_, Type::Multi(options)) => options.iter().any(|t| compatible(input, t, types)),
+        (Type::Custom(_), Type::Custom(_)) => structurally_eq(input, output, types, 0),
+        (Type::List(a), Type::List(b)) => compatible(a, b, types),
+        (Type::Map(ka, va), Type::Map(kb, vb)) => {
+            compatible(ka, kb, types) && compatible(va, vb, types)
+        }
+        (Type::Optional(a), Type::Optional(b)) => compatible(a, b, types),
+        (Type::Optional(a), _) => compatible(a, output, types),
+        _ => false,
+    }
+}
+
+fn structurally_eq(a: &Type, b: &Type, types: &[CustomType], depth: usize) -> bool {
+    if depth > MAX_DEPTH {
+        return false;
+    }
+    match (a, b) {
+        (Type::Custom(a), Type::Custom(b)) => {
+            if a == b {
+                return true;
+            }
+            let (Some(a), Some(b)) = (find(types, a), find(types, b)) else {
+                return false;
+            };
+            a.fields.len() == b.fields.len()
+                && a.fields.iter().zip(&b.fields).all(|(a, b)| {
+                    a.name == b.name
+                        && structurally_eq(&a.data_type, &b.data_type, types, depth + 1)
+                })
+        }
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compatible, default_var, matches_shape, CustomField, CustomType};
+    use crate::graph::{Type, Var};
+
+    fn vec2(name: &str) -> CustomType {
+        CustomType {
+            name: name.to_string(),
+            fields: ["x", "y"]
+                .iter()
+                .map(|n| CustomField {
+                    name: n.to_string(),
+                    data_type: Type::Float,
+                    default: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_default_value() {
+        let types = vec![vec2("Vec2")];
+        let value = default_var(&Type::Custom("Vec2".to_string()), &types).unwrap();
+        assert_eq!(value.to_string(), "Vec2 { x: 0, y: 0 }");
+        assert!(matches_shape(
+            &value,
+            &Type::Custom("Vec2".to_string()),
+            &types
+        ));
+        assert!(default_var(&Type::Custom("Missing".to_string()), &types).is_none());
+    }
+
+    #[test]
+    fn test_structural_compatibility() {
+        let mut size = vec2("Size");
+        size.fields[0].name = "w".to_string();
+        let types = vec![vec2("Vec2"), vec2("Point"), size];
+
+        let vec2 = Type::Custom("Vec2".to_string());
+        assert!(compatible(
+            &vec2,
+            &Type::Custom("Point".to_string()),
+            &types
+        ));
+        assert!(!compatible(
+            &vec2,
+            &Type::Custom("Size".to_string()),
+            &types
+        ));
+        assert!(!compatible(&vec2, &Type::Float, &types));
+        assert!(compatible(&Type::Float, &Var::Float(1.0).type_(), &[]));
+
+        let points = Type::List(Box::new(Type::Custom("Point".to_string())));
+        assert!(compatible(&Type::List(Box::new(vec2)), &points, &types));
+    }
+}
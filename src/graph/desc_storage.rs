@@ -1,23 +1,69 @@
 use std::path::PathBuf;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, info, warn};
 
-use super::NodeDesc;
+use super::{CustomType, NodeDesc, PortDesc, Type};
 
 const STD_LIBS: &[&[u8]] = &[
     include_bytes!("../../std/math.no3lib.yaml"),
     include_bytes!("../../std/string.no3lib.yaml"),
     include_bytes!("../../std/debug.no3lib.yaml"),
+    include_bytes!("../../std/variables.no3lib.yaml"),
+    include_bytes!("../../std/graph.no3lib.yaml"),
+    include_bytes!("../../std/flow.no3lib.yaml"),
 ];
 
+fn default_version() -> Version {
+    Version::new(0, 0, 0)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibDependency {
+    pub category: String,
+    pub version: VersionReq,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct DescLib {
     pub category: String,
     pub lib: String,
+    #[serde(default = "default_version")]
+    pub version: Version,
+    #[serde(default)]
+    pub depends_on: Vec<LibDependency>,
+    #[serde(default)]
+    pub types: Vec<CustomType>,
     #[serde(rename = "nodes")]
     pub descs: Vec<NodeDesc>,
+    #[serde(skip)]
+    pub source: Option<PathBuf>, // File the library was loaded from, None for std libraries
+}
+
+/// Identifies the descriptor a node was created from and the library version it came from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DescRef {
+    pub category: String,
+    pub title: String,
+    pub version: Version,
+}
+
+impl DescRef {
+    /// Nodes of workspaces saved before descriptor references have none,
+    /// until `DescStorage::find_title` fills them in.
+    pub fn unknown() -> Self {
+        Self {
+            category: String::new(),
+            title: String::new(),
+            version: default_version(),
+        }
+    }
+
+    pub fn is_unknown(&self) -> bool {
+        self.category.is_empty()
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -34,18 +80,78 @@ impl DescStorage {
         self.libs.iter().map(|lib| lib.category.clone()).collect()
     }
 
-    pub fn descs_category(&self, category: &str) -> Option<&Vec<NodeDesc>> {
-        self.libs
+    pub fn lib(&self, category: &str) -> Option<&DescLib> {
+        self.libs.iter().find(|lib| lib.category == category)
+    }
+
+    pub fn desc_ref(&self, category: &str, title: &str) -> Option<DescRef> {
+        let lib = self.lib(category)?;
+        lib.descs
+            .iter()
+            .find(|desc| desc.title == title)
+            .map(|desc| DescRef {
+                category: lib.category.clone(),
+                title: desc.title.clone(),
+                version: lib.version.clone(),
+            })
+    }
+
+    /// The descriptor titled like `desc` in any library. If several libraries
+    /// have one, the one equal to `desc` wins, then one with the same ports.
+    pub fn find_title(&self, desc: &NodeDesc) -> Option<DescRef> {
+        let candidates: Vec<(DescRef, &NodeDesc)> = self
+            .libs
+            .iter()
+            .filter_map(|lib| {
+                let desc_ref = self.desc_ref(&lib.category, &desc.title)?;
+                let found = self.resolve(&desc_ref)?;
+                Some((desc_ref, found))
+            })
+            .collect();
+        let ports = |d: &NodeDesc| {
+            let names = |ports: &[PortDesc]| -> Vec<String> {
+                ports.iter().map(|p| p.name.clone()).collect()
+            };
+            (names(&d.inputs), names(&d.outputs))
+        };
+        let hash = desc.structure_hash();
+        let best = candidates
             .iter()
-            .find(|lib| lib.category == category)
-            .map(|lib| &lib.descs)
+            .find(|(_, found)| found.structure_hash() == hash)
+            .or_else(|| {
+                candidates
+                    .iter()
+                    .find(|(_, found)| ports(found) == ports(desc))
+            });
+        if best.is_none() && candidates.len() > 1 {
+            warn!(
+                "{} is in several libraries, using the one in '{}'",
+                desc.title, candidates[0].0.category
+            );
+        }
+        best.or(candidates.first())
+            .map(|(desc_ref, _)| desc_ref.clone())
+    }
+
+    pub fn resolve(&self, desc_ref: &DescRef) -> Option<&NodeDesc> {
+        self.desc(&desc_ref.category, &desc_ref.title)
+    }
+
+    pub fn descs_category(&self, category: &str) -> Option<&Vec<NodeDesc>> {
+        self.lib(category).map(|lib| &lib.descs)
     }
 
     pub fn desc(&self, category: &str, title: &str) -> Option<&NodeDesc> {
+        self.lib(category)
+            .and_then(|lib| lib.descs.iter().find(|desc| desc.title == title))
+    }
+
+    /// Custom types declared by every loaded library.
+    pub fn custom_types(&self) -> Vec<CustomType> {
         self.libs
             .iter()
-            .find(|lib| lib.category == category)
-            .and_then(|lib| lib.descs.iter().find(|desc| desc.title == title))
+            .flat_map(|lib| lib.types.iter().cloned())
+            .collect()
     }
 
     pub fn desc_count(&self) -> usize {
@@ -57,54 +163,403 @@ impl DescStorage {
     }
 
     pub fn import_std_libs(&mut self) -> Result<()> {
-        for lib_bytes in STD_LIBS {
-            self.import(String::from_utf8_lossy(lib_bytes).to_string(), true)?;
-        }
-        Ok(())
+        let yamls = STD_LIBS
+            .iter()
+            .map(|lib_bytes| String::from_utf8_lossy(lib_bytes).to_string())
+            .collect();
+        self.import_many(yamls, true)
     }
 
     pub fn load_import(&mut self, yaml_path: PathBuf, upgrade: bool) -> Result<()> {
-        debug!(?yaml_path, "Loading desc lib");
-        let yaml =
-            std::fs::read_to_string(&yaml_path).context("Failed to read desc lib YAML file")?;
-        self.import(yaml, upgrade)?;
+        self.load_import_many(vec![yaml_path], upgrade)
+    }
 
-        Ok(())
+    pub fn load_import_many(&mut self, yaml_paths: Vec<PathBuf>, upgrade: bool) -> Result<()> {
+        let mut libs = Vec::new();
+        for yaml_path in yaml_paths {
+            debug!(?yaml_path, "Loading desc lib");
+            let yaml = std::fs::read_to_string(&yaml_path)
+                .context(format!("Failed to read desc lib YAML file {:?}", yaml_path))?;
+            let mut lib = Self::parse(&yaml)?;
+            lib.source = Some(yaml_path);
+            libs.push(lib);
+        }
+        self.import_libs(libs, upgrade)
+    }
+
+    /// Imports each library on its own, so one that fails does not keep the
+    /// others out. Failed libraries are retried while others succeed, as they
+    /// may depend on them. Returns the libraries that failed.
+    pub fn load_import_each(
+        &mut self,
+        yaml_paths: Vec<PathBuf>,
+        upgrade: bool,
+    ) -> Vec<(PathBuf, anyhow::Error)> {
+        let mut pending = yaml_paths;
+        loop {
+            let count = pending.len();
+            let mut failed = Vec::new();
+            for yaml_path in pending {
+                if let Err(e) = self.load_import(yaml_path.clone(), upgrade) {
+                    failed.push((yaml_path, e));
+                }
+            }
+            if failed.is_empty() || failed.len() == count {
+                return failed;
+            }
+            pending = failed.into_iter().map(|(path, _)| path).collect();
+        }
     }
 
-    #[tracing::instrument(skip_all)]
     pub fn import(&mut self, yaml: String, upgrade: bool) -> Result<()> {
+        self.import_many(vec![yaml], upgrade)
+    }
+
+    /// Parses a batch of libraries and imports them in dependency order, so a
+    /// library may depend on another one from the same batch.
+    pub fn import_many(&mut self, yamls: Vec<String>, upgrade: bool) -> Result<()> {
+        let libs = yamls
+            .iter()
+            .map(|yaml| Self::parse(yaml))
+            .collect::<Result<Vec<_>>>()?;
+        self.import_libs(libs, upgrade)
+    }
+
+    fn parse(yaml: &str) -> Result<DescLib> {
         let lib: DescLib =
-            serde_yaml_ng::from_str(&yaml).context("Failed to parse desc lib from YAML")?;
-        debug!(category=%lib.category, lib=%lib.lib, desc_count=%lib.descs.len(), "Parsed desc lib");
+            serde_yaml_ng::from_str(yaml).context("Failed to parse desc lib from YAML")?;
+        debug!(category=%lib.category, lib=%lib.lib, version=%lib.version, desc_count=%lib.descs.len(), "Parsed desc lib");
+        Ok(lib)
+    }
+
+    /// Imports all of `libs` or, if one of them fails, none of them.
+    #[tracing::instrument(skip_all)]
+    fn import_libs(&mut self, libs: Vec<DescLib>, upgrade: bool) -> Result<()> {
+        let mut staged = self.clone();
+        for lib in Self::resolve_order(libs)? {
+            staged.add_lib(lib, upgrade)?;
+        }
+        *self = staged;
+        Ok(())
+    }
+
+    /// Orders `libs` so that every library comes after the ones it depends on.
+    /// Dependencies that are not part of the batch are expected to be loaded already.
+    fn resolve_order(mut libs: Vec<DescLib>) -> Result<Vec<DescLib>> {
+        for (i, lib) in libs.iter().enumerate() {
+            if let Some(other) = libs[..i].iter().find(|l| l.category == lib.category) {
+                bail!(
+                    "Conflicting versions of '{}' in one import: {} and {}",
+                    lib.category,
+                    other.version,
+                    lib.version
+                );
+            }
+        }
+
+        let mut ordered: Vec<DescLib> = Vec::new();
+        while !libs.is_empty() {
+            let ready = libs.iter().position(|lib| {
+                lib.depends_on.iter().all(|dep| {
+                    !libs
+                        .iter()
+                        .any(|l| l.category == dep.category && l.category != lib.category)
+                })
+            });
+            match ready {
+                Some(index) => ordered.push(libs.remove(index)),
+                None => {
+                    let cycle: Vec<String> = libs.iter().map(|l| l.category.clone()).collect();
+                    bail!(
+                        "Circular dependency between libraries: {}",
+                        cycle.join(", ")
+                    );
+                }
+            }
+        }
+        Ok(ordered)
+    }
+
+    fn add_lib(&mut self, lib: DescLib, upgrade: bool) -> Result<()> {
+        for dep in &lib.depends_on {
+            if dep.category == lib.category {
+                bail!("Library '{}' depends on itself", lib.category);
+            }
+            let loaded = self.lib(&dep.category).ok_or_else(|| {
+                anyhow!(
+                    "Library '{}' requires '{}' {}, which is not loaded",
+                    lib.category,
+                    dep.category,
+                    dep.version
+                )
+            })?;
+            if !dep.version.matches(&loaded.version) {
+                bail!(
+                    "Library '{}' requires '{}' {}, but version {} is loaded",
+                    lib.category,
+                    dep.category,
+                    dep.version,
+                    loaded.version
+                );
+            }
+        }
+
+        for desc in &lib.descs {
+            for port in desc.inputs.iter().chain(&desc.outputs) {
+                if let Some(scope) = &port.scope
+                    && !desc
+                        .outputs
+                        .iter()
+                        .any(|o| &o.name == scope && o.data_type == Type::Flow)
+                {
+                    bail!(
+                        "Port '{}' of node '{}' in library '{}' belongs to '{}', which is not a flow output",
+                        port.name,
+                        desc.title,
+                        lib.category,
+                        scope
+                    );
+                }
+            }
+            for field in &desc.fields {
+                field.validate().with_context(|| {
+                    format!(
+                        "Invalid field '{}' of node '{}' in library '{}'",
+                        field.name, desc.title, lib.category
+                    )
+                })?;
+            }
+        }
 
-        if self.lib_exists(&lib.category) {
+        for custom_type in &lib.types {
+            if let Some(other) = self.libs.iter().find(|l| {
+                l.category != lib.category
+                    && l.types
+                        .iter()
+                        .any(|t| t.name == custom_type.name && t != custom_type)
+            }) {
+                bail!(
+                    "Library '{}' declares type '{}', which '{}' already declares differently",
+                    lib.category,
+                    custom_type.name,
+                    other.category
+                );
+            }
+        }
+
+        if let Some(existing) = self.lib(&lib.category) {
             if !upgrade {
                 warn!(
                     "A desc lib with category '{}' already exists, skipping import",
                     lib.category
                 );
                 bail!("A desc lib with category '{}' already exists", lib.category);
-            } else {
-                warn!(
-                    "A desc lib with category '{}' already exists, upgrading",
-                    lib.category
-                );
-                let original_count = self.desc_count();
-                self.libs.retain(|l| l.category != lib.category);
-                let removed_count = original_count - self.desc_count();
-                debug!(removed_count, "Removed descs from existing lib");
             }
+
+            for dependent in &self.libs {
+                if let Some(dep) = dependent
+                    .depends_on
+                    .iter()
+                    .find(|dep| dep.category == lib.category)
+                    && !dep.version.matches(&lib.version)
+                {
+                    bail!(
+                        "Cannot upgrade '{}' to {}: '{}' requires {}",
+                        lib.category,
+                        lib.version,
+                        dependent.category,
+                        dep.version
+                    );
+                }
+            }
+
+            warn!(
+                "A desc lib with category '{}' already exists ({}), upgrading to {}",
+                lib.category, existing.version, lib.version
+            );
+            let original_count = self.desc_count();
+            self.libs.retain(|l| l.category != lib.category);
+            let removed_count = original_count - self.desc_count();
+            debug!(removed_count, "Removed descs from existing lib");
         }
 
-        self.libs.push(lib.clone());
         info!(
             category=%lib.category,
             lib=%lib.lib,
+            version=%lib.version,
             desc_count=%lib.descs.len(),
             "Imported desc lib successfully"
         );
+        self.libs.push(lib);
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::DescStorage;
+
+    fn lib_yaml(category: &str, version: &str, depends_on: &[(&str, &str)]) -> String {
+        let mut yaml = format!("category: \"{category}\"\nlib: \"Test\"\nversion: \"{version}\"\n");
+        if !depends_on.is_empty() {
+            yaml.push_str("depends_on:\n");
+            for (dep, req) in depends_on {
+                yaml.push_str(&format!("  - category: \"{dep}\"\n    version: \"{req}\"\n"));
+            }
+        }
+        yaml.push_str("nodes: []\n");
+        yaml
+    }
+
+    #[test]
+    fn test_std_libs() {
+        let mut storage = DescStorage::new();
+        storage.import_std_libs().unwrap();
+        assert!(storage.lib_exists("Math"));
+        assert!(storage.desc("Math", "Add").is_some());
+        assert_eq!(storage.custom_types()[0].name, "Vec2");
+    }
+
+    #[test]
+    fn test_dependency_order() {
+        let mut storage = DescStorage::new();
+        storage
+            .import_many(
+                vec![
+                    lib_yaml("Geometry", "1.0.0", &[("Math", ">=1.0")]),
+                    lib_yaml("Math", "1.2.0", &[]),
+                ],
+                false,
+            )
+            .unwrap();
+        assert_eq!(storage.categories(), vec!["Math", "Geometry"]);
+    }
+
+    #[test]
+    fn test_missing_dependency() {
+        let mut storage = DescStorage::new();
+        let yaml = lib_yaml("Geometry", "1.0.0", &[("Math", ">=1.0")]);
+        assert!(storage.import(yaml, false).is_err());
+        assert!(!storage.lib_exists("Geometry"));
+    }
+
+    #[test]
+    fn test_unsatisfied_dependency() {
+        let mut storage = DescStorage::new();
+        storage.import(lib_yaml("Math", "0.9.0", &[]), false).unwrap();
+        let yaml = lib_yaml("Geometry", "1.0.0", &[("Math", ">=1.0")]);
+        assert!(storage.import(yaml, false).is_err());
+    }
+
+    #[test]
+    fn test_conflicting_upgrade() {
+        let mut storage = DescStorage::new();
+        storage
+            .import_many(
+                vec![
+                    lib_yaml("Math", "1.0.0", &[]),
+                    lib_yaml("Geometry", "1.0.0", &[("Math", "^1.0")]),
+                ],
+                false,
+            )
+            .unwrap();
+        assert!(storage.import(lib_yaml("Math", "2.0.0", &[]), true).is_err());
+        assert!(storage.import(lib_yaml("Math", "1.1.0", &[]), true).is_ok());
+        assert_eq!(storage.lib("Math").unwrap().version.to_string(), "1.1.0");
+    }
+
+    #[test]
+    fn test_conflict_in_batch() {
+        let mut storage = DescStorage::new();
+        let result = storage.import_many(
+            vec![lib_yaml("Math", "1.0.0", &[]), lib_yaml("Math", "2.0.0", &[])],
+            true,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_failed_batch() {
+        let mut storage = DescStorage::new();
+        let result = storage.import_many(
+            vec![
+                lib_yaml("Math", "1.0.0", &[]),
+                lib_yaml("Geometry", "1.0.0", &[("Math", "^2.0")]),
+            ],
+            false,
+        );
+        assert!(result.is_err());
+        assert!(storage.libs.is_empty());
+    }
+
+    #[test]
+    fn test_import_each() {
+        // One directory per run, so parallel runs don't read each other's files
+        let dir = std::env::temp_dir().join(format!("no3_test_import_each_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = [
+            (
+                "a.no3lib.yaml",
+                lib_yaml("Geometry", "1.0.0", &[("Math", "^1.0")]),
+            ),
+            ("b.no3lib.yaml", "category: [".to_string()),
+            ("c.no3lib.yaml", lib_yaml("Math", "1.0.0", &[])),
+        ];
+        for (file, yaml) in &files {
+            std::fs::write(dir.join(file), yaml).unwrap();
+        }
+
+        let mut storage = DescStorage::new();
+        let failed =
+            storage.load_import_each(files.iter().map(|(f, _)| dir.join(f)).collect(), false);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].0.ends_with("b.no3lib.yaml"));
+        assert_eq!(storage.categories(), vec!["Math", "Geometry"]);
+    }
+
+    #[test]
+    fn test_find_title() {
+        let lib = |category: &str, output: &str| {
+            format!(
+                r#"category: "{category}"
+lib: "Test"
+nodes:
+  - title: "Const"
+    desc: ""
+    end: false
+    fields:
+    inputs:
+    outputs:
+      - name: "{output}"
+        type: !Int
+    impls:
+"#
+            )
+        };
+        let mut storage = DescStorage::new();
+        storage
+            .import_many(vec![lib("A", "a"), lib("B", "b")], false)
+            .unwrap();
+
+        let saved = storage.desc("B", "Const").unwrap().clone();
+        assert_eq!(storage.find_title(&saved).unwrap().category, "B");
+        let mut renamed = saved.clone();
+        renamed.desc = "Saved by an older version".to_string();
+        assert_eq!(storage.find_title(&renamed).unwrap().category, "B");
+    }
+
+    #[test]
+    fn test_circular_dependency() {
+        let mut storage = DescStorage::new();
+        let result = storage.import_many(
+            vec![
+                lib_yaml("A", "1.0.0", &[("B", "*")]),
+                lib_yaml("B", "1.0.0", &[("A", "*")]),
+            ],
+            false,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use anyhow::{bail, Result};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use super::{Connection, DescStorage, Node, Var, Variable};
+
+/// The saved form of a workspace. Nodes refer to their descriptors by library
+/// and title instead of copying them, and everything is kept in a stable
+/// order, so saved workspaces diff and merge well.
+#[derive(Serialize, Deserialize)]
+pub struct Document {
+    pub version: String,
+    pub libraries: Vec<LibraryRef>, // Libraries the nodes come from
+    pub nodes: Vec<NodeRef>,
+    pub connections: Vec<ConnectionRef>,
+    #[serde(default)]
+    pub variables: Vec<Variable>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LibraryRef {
+    pub category: String,
+    pub version: Version, // Loaded when the workspace was saved
+    /// File to load the library from when it is neither a std library nor in
+    /// a search directory. Relative to the workspace if it is next to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NodeRef {
+    pub id: usize,
+    pub category: String,
+    pub title: String,
+    pub pos: (f32, f32),
+    #[serde(default)]
+    pub fields: BTreeMap<String, Var>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConnectionRef {
+    pub from: PortRef,
+    pub to: PortRef,
+}
+
+/// A port by name, so connections survive ports being reordered.
+#[derive(Serialize, Deserialize)]
+pub struct PortRef {
+    pub node: usize,
+    pub port: String,
+}
+
+impl Document {
+    /// `dir` is the directory the document is saved to. Libraries in
+    /// `search_dirs` are found again without a path.
+    pub fn new(
+        nodes: &[Node],
+        connections: &[Connection],
+        variables: &[Variable],
+        storage: &DescStorage,
+        dir: &Path,
+        search_dirs: &[PathBuf],
+    ) -> Self {
+        let mut libraries: Vec<LibraryRef> = Vec::new();
+        for node in nodes {
+            let category = &node.desc_ref.category;
+            if libraries.iter().any(|l| &l.category == category) {
+                continue;
+            }
+            let lib = storage.lib(category);
+            let path = lib
+                .and_then(|lib| lib.source.as_ref())
+                .filter(|source| !search_dirs.iter().any(|dir| source.parent() == Some(dir)))
+                .map(|source| source.strip_prefix(dir).unwrap_or(source).to_path_buf());
+            libraries.push(LibraryRef {
+                category: category.clone(),
+                version: lib.map_or(node.desc_ref.version.clone(), |l| l.version.clone()),
+                path,
+            });
+        }
+        libraries.sort_by(|a, b| a.category.cmp(&b.category));
+
+        let mut node_refs: Vec<NodeRef> = nodes
+            .iter()
+            .map(|node| NodeRef {
+                id: node.id,
+                category: node.desc_ref.category.clone(),
+                title: node.desc_ref.title.clone(),
+                pos: node.pos,
+                fields: node
+                    .desc
+                    .fields
+                    .iter()
+                    .map(|f| (f.name.clone(), f.value.clone()))
+                    .collect(),
+            })
+            .collect();
+        node_refs.sort_by_key(|n| n.id);
+
+        let port = |(id, index): (usize, usize), output: bool| {
+            let node = nodes.iter().find(|n| n.id == id)?;
+            let ports = if output {
+                &node.desc.outputs
+            } else {
+                &node.desc.inputs
+            };
+            Some(PortRef {
+                node: id,
+                port: ports.get(index)?.name.clone(),
+            })
+        };
+        let mut sorted: Vec<&Connection> = connections.iter().collect();
+        sorted.sort_by_key(|c| (c.to, c.from));
+        let connections = sorted
+            .into_iter()
+            .filter_map(|c| {
+                Some(ConnectionRef {
+                    from: port(c.from, true)?,
+                    to: port(c.to, false)?,
+                })
+            })
+            .collect();
+
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            libraries,
+            nodes: node_refs,
+            connections,
+            variables: variables.to_vec(),
+        }
+    }
+
+    /// The first field value or variable holding NaN or an infinity, which
+    /// JSON cannot store.
+    pub fn non_finite(&self) -> Option<String> {
+        for node in &self.nodes {
+            if let Some((name, _)) = node.fields.iter().find(|(_, value)| !value.is_finite()) {
+                return Some(format!("{}#{} field '{}'", node.title, node.id, name));
+            }
+        }
+        self.variables
+            .iter()
+            .find(|variable| !variable.value.is_finite())
+            .map(|variable| format!("Variable '{}'", variable.name))
+    }
+
+    /// Builds the nodes and connections from the descriptors in `storage`.
+    /// Fields and connections the descriptors no longer have are dropped.
+    /// Returns a description of every dropped item and of every library
+    /// loaded at another version than the one saved.
+    pub fn into_graph(
+        self,
+        storage: &DescStorage,
+    ) -> Result<(Vec<Node>, Vec<Connection>, Vec<String>)> {
+        let mut changes = Vec::new();
+        let mut missing = Vec::new();
+        for library in &self.libraries {
+            match storage.lib(&library.category) {
+                Some(lib) if lib.version != library.version => changes.push(format!(
+                    "'{}' was saved at {}, loaded at {}",
+                    library.category, library.version, lib.version
+                )),
+                Some(_) => {}
+                None => missing.push(format!("'{}' {}", library.category, library.version)),
+            }
+        }
+        if !missing.is_empty() {
+            bail!("Libraries are not loaded: {}", missing.join(", "));
+        }
+
+        let mut node_refs = self.nodes;
+        node_refs.sort_by_key(|n| n.id);
+        let mut nodes: Vec<Node> = Vec::new();
+        for (index, node_ref) in node_refs.into_iter().enumerate() {
+            if node_ref.id != index {
+                bail!("Node IDs must be 0 to {}, found {}", index, node_ref.id);
+            }
+            let (Some(desc_ref), Some(desc)) = (
+                storage.desc_ref(&node_ref.category, &node_ref.title),
+                storage.desc(&node_ref.category, &node_ref.title),
+            ) else {
+                bail!(
+                    "'{}' does not provide {} (node #{})",
+                    node_ref.category,
+                    node_ref.title,
+                    node_ref.id
+                );
+            };
+            let mut desc = desc.clone();
+            for (name, value) in node_ref.fields {
+                match desc.fields.iter_mut().find(|f| f.name == name) {
+                    Some(field) if field.data_type.accepts(&value.type_()) => field.value = value,
+                    Some(field) => changes.push(format!(
+                        "{}#{}: dropped {} value of {} field '{}'",
+                        desc.title,
+                        node_ref.id,
+                        value.type_(),
+                        field.data_type,
+                        name
+                    )),
+                    None => changes.push(format!(
+                        "{}#{}: dropped unknown field '{}'",
+                        desc.title, node_ref.id, name
+                    )),
+                }
+            }
+            for field in &mut desc.fields {
+                field.normalize();
+                field.reset_raw();
+            }
+            let mut node = Node::with_desc(node_ref.id, desc_ref, desc);
+            node.pos = node_ref.pos;
+            nodes.push(node);
+        }
+
+        let index = |port: &PortRef, output: bool| {
+            let node = nodes.get(port.node)?;
+            let ports = if output {
+                &node.desc.outputs
+            } else {
+                &node.desc.inputs
+            };
+            ports.iter().position(|p| p.name == port.port)
+        };
+        let mut connections = Vec::new();
+        for connection in &self.connections {
+            match (index(&connection.from, true), index(&connection.to, false)) {
+                (Some(from), Some(to)) => connections.push(Connection {
+                    from: (connection.from.node, from),
+                    to: (connection.to.node, to),
+                }),
+                _ => changes.push(format!(
+                    "Dropped connection from #{} '{}' to #{} '{}'",
+                    connection.from.node,
+                    connection.from.port,
+                    connection.to.node,
+                    connection.to.port
+                )),
+            }
+        }
+        Ok((nodes, connections, changes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use semver::Version;
+
+    use super::{ConnectionRef, Document, PortRef};
+    use crate::graph::{Connection, DescStorage, Node, Var};
+
+    #[test]
+    fn test_round_trip() {
+        let mut storage = DescStorage::new();
+        storage.import_std_libs().unwrap();
+        let node = |id: usize, category: &str, title: &str| {
+            let mut node = Node::new(&storage, id, category, title).unwrap();
+            node.pos = (id as f32 * 100.0, 0.0);
+            node
+        };
+        let mut nodes = vec![node(0, "Math", "Const"), node(1, "Math", "Add")];
+        nodes[0].desc.fields[0].value = Var::Float(1.5);
+        let connections = vec![Connection {
+            from: (0, 0),
+            to: (1, 1),
+        }];
+
+        let document = Document::new(&nodes, &connections, &[], &storage, Path::new("."), &[]);
+        let ron = ron::ser::to_string_pretty(&document, Default::default()).unwrap();
+        assert!(!ron.contains("impls"));
+        assert!(ron.contains("port: \"B\""));
+
+        let mut document: Document = ron::from_str(&ron).unwrap();
+        let (loaded, connections, changes) = document.into_graph(&storage).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert!(matches!(loaded[0].desc.fields[0].value, Var::Float(v) if v == 1.5));
+        assert_eq!((connections[0].from, connections[0].to), ((0, 0), (1, 1)));
+        assert!(changes.is_empty());
+
+        document = ron::from_str(&ron).unwrap();
+        document.libraries[0].version = Version::new(0, 1, 0);
+        document.nodes[0]
+            .fields
+            .insert("gone".to_string(), Var::Int(1));
+        document.connections.push(ConnectionRef {
+            from: PortRef {
+                node: 0,
+                port: "gone".to_string(),
+            },
+            to: PortRef {
+                node: 1,
+                port: "A".to_string(),
+            },
+        });
+        let (_, connections, changes) = document.into_graph(&storage).unwrap();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(changes.len(), 3);
+        assert!(changes[0].contains("saved at 0.1.0"));
+    }
+}
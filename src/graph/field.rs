@@ -1,10 +1,64 @@
+use anyhow::{bail, Context, Result};
+use eframe::egui::Color32;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
 
 use super::{Type, Var};
 
+/// The widget used to edit a field.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum FieldKind {
     Enter,
+    Slider {
+        min: f64,
+        max: f64,
+        #[serde(default)]
+        step: Option<f64>,
+    },
+    Drag {
+        #[serde(default)]
+        speed: Option<f64>,
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+    Dropdown(Vec<String>), // Choices
+    MultiLine,
+    Code(String), // Language used for highlighting, e.g. "python"
+    Color,        // Hex string, e.g. "#ff8800ff"
+    FilePath {
+        #[serde(default)]
+        extensions: Vec<String>,
+    },
+    Variable, // Name of a workspace variable
+}
+
+/// Constraints a library puts on a field's value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FieldConstraints {
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    #[serde(default)]
+    pub pattern: Option<String>, // Regex String values must match
+    #[serde(default)]
+    pub non_empty: bool,
+    #[serde(skip)]
+    regex: OnceLock<Result<Regex, String>>, // `pattern`, compiled on first use
+}
+
+impl FieldConstraints {
+    /// The compiled `pattern`, or why it doesn't compile.
+    pub fn regex(&self) -> Option<&Result<Regex, String>> {
+        let pattern = self.pattern.as_ref()?;
+        Some(
+            self.regex
+                .get_or_init(|| Regex::new(pattern).map_err(|e| e.to_string())),
+        )
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,5 +69,388 @@ pub struct FieldDesc {
     pub value: Var,
     #[serde(skip)]
     pub raw_value: String,
+    #[serde(skip)]
+    pub parse_error: Option<String>, // Set while `raw_value` can't be parsed
     pub kind: FieldKind,
+    #[serde(default)]
+    pub constraints: FieldConstraints,
+}
+
+fn is_numeric(data_type: &Type) -> bool {
+    match data_type {
+        Type::Int | Type::Float => true,
+        Type::Multi(types) => !types.is_empty() && types.iter().all(is_numeric),
+        _ => false,
+    }
+}
+
+impl FieldDesc {
+    /// Checks that the kind can edit the field's type and the value is valid for it.
+    pub fn validate(&self) -> Result<()> {
+        let numeric = || {
+            if !is_numeric(&self.data_type) {
+                bail!("{:?} fields must be Int or Float", self.kind);
+            }
+            Ok(())
+        };
+        let string = || {
+            if self.data_type != Type::String {
+                bail!("{:?} fields must be String", self.kind);
+            }
+            Ok(())
+        };
+        match &self.kind {
+            FieldKind::Enter => {}
+            FieldKind::Slider { min, max, step } => {
+                numeric()?;
+                if min >= max {
+                    bail!("Slider min ({}) must be less than max ({})", min, max);
+                }
+                if step.is_some_and(|s| s <= 0.0) {
+                    bail!("Slider step must be positive");
+                }
+            }
+            FieldKind::Drag { speed, min, max } => {
+                numeric()?;
+                if let (Some(min), Some(max)) = (min, max)
+                    && min > max
+                {
+                    bail!("Drag min ({}) is greater than max ({})", min, max);
+                }
+                if speed.is_some_and(|s| s <= 0.0) {
+                    bail!("Drag speed must be positive");
+                }
+            }
+            FieldKind::Dropdown(choices) => {
+                string()?;
+                if choices.is_empty() {
+                    bail!("Dropdown has no choices");
+                }
+                if let Var::String(value) = &self.value
+                    && !choices.contains(value)
+                {
+                    bail!("Dropdown value '{}' is not one of its choices", value);
+                }
+            }
+            FieldKind::MultiLine
+            | FieldKind::Code(_)
+            | FieldKind::FilePath { .. }
+            | FieldKind::Variable => string()?,
+            FieldKind::Color => {
+                string()?;
+                if let Var::String(value) = &self.value
+                    && Color32::from_hex(value).is_err()
+                {
+                    bail!("'{}' is not a hex color", value);
+                }
+            }
+        }
+        if !self.data_type.accepts(&self.value.type_()) {
+            bail!("Value {} is not a {}", self.value, self.data_type);
+        }
+        if let (Some(min), Some(max)) = (self.constraints.min, self.constraints.max)
+            && min > max
+        {
+            bail!("Constraint min ({}) is greater than max ({})", min, max);
+        }
+        if let Some(pattern) = &self.constraints.pattern {
+            Regex::new(pattern).context("Invalid pattern")?;
+        }
+        Ok(())
+    }
+
+    /// Why the current value is invalid, if it is: either the entered text
+    /// couldn't be parsed or the value breaks one of the constraints.
+    pub fn error(&self) -> Option<String> {
+        if let Some(e) = &self.parse_error {
+            return Some(e.clone());
+        }
+        if let Some(key) = self.value.duplicate_key() {
+            return Some(format!("Key {} is used more than once", key));
+        }
+        let constraints = &self.constraints;
+        let number = match &self.value {
+            Var::Int(i) => Some(*i as f64),
+            Var::Float(f) => Some(*f),
+            _ => None,
+        };
+        if let Some(number) = number {
+            if let Some(min) = constraints.min
+                && number < min
+            {
+                return Some(format!("Must be at least {}", min));
+            }
+            if let Some(max) = constraints.max
+                && number > max
+            {
+                return Some(format!("Must be at most {}", max));
+            }
+        }
+        if let Var::String(s) = &self.value {
+            if constraints.non_empty && s.is_empty() {
+                return Some("Must not be empty".to_string());
+            }
+            match constraints.regex() {
+                Some(Ok(regex)) if !regex.is_match(s) => {
+                    return Some(format!("Must match {}", regex.as_str()));
+                }
+                Some(Err(e)) => return Some(format!("Invalid pattern: {}", e)),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Parses `raw_value` as one of the field's types. On failure the previous
+    /// value is kept and `parse_error` explains what's wrong.
+    pub fn parse_raw(&mut self) {
+        let types = match &self.data_type {
+            Type::Multi(types) => types.clone(),
+            data_type => vec![data_type.clone()],
+        };
+        let raw = self.raw_value.trim();
+        let mut parsed = None;
+        // The most specific type first, so "1" in an [Int, Float, String]
+        // field becomes an Int and "1.0" a Float: the text alone decides.
+        for data_type in [Type::Int, Type::Float, Type::Bool, Type::String] {
+            if !types.contains(&data_type) {
+                continue;
+            }
+            parsed = match data_type {
+                Type::Float => raw.parse::<f64>().ok().map(Var::Float),
+                Type::Int => raw.parse::<i64>().ok().map(Var::Int),
+                Type::Bool => raw.parse::<bool>().ok().map(Var::Bool),
+                _ => Some(Var::String(self.raw_value.clone())),
+            };
+            if parsed.is_some() {
+                break;
+            }
+        }
+
+        match parsed {
+            Some(value) => {
+                self.value = value;
+                self.parse_error = None;
+                if !matches!(self.value, Var::String(_)) {
+                    self.raw_value = raw_text(&self.value);
+                }
+            }
+            None if raw.is_empty() => {
+                self.parse_error = Some("A value is required".to_string());
+            }
+            None => {
+                self.parse_error = Some(format!("'{}' is not a {}", raw, self.data_type));
+            }
+        }
+    }
+
+    /// Brings a value loaded from a workspace back into the range the kind
+    /// allows, e.g. clamping sliders and resetting unknown dropdown choices.
+    pub fn normalize(&mut self) {
+        let (min, max) = match &self.kind {
+            FieldKind::Slider { min, max, .. } => (Some(*min), Some(*max)),
+            FieldKind::Drag { min, max, .. } => (*min, *max),
+            FieldKind::Dropdown(choices) => {
+                if !matches!(&self.value, Var::String(v) if choices.contains(v))
+                    && let Some(first) = choices.first()
+                {
+                    self.value = Var::String(first.clone());
+                }
+                (None, None)
+            }
+            FieldKind::Color => {
+                if !matches!(&self.value, Var::String(v) if Color32::from_hex(v).is_ok()) {
+                    self.value = Var::String(Color32::WHITE.to_hex());
+                }
+                (None, None)
+            }
+            _ => (None, None),
+        };
+        let min = min.unwrap_or(f64::NEG_INFINITY);
+        let max = max.unwrap_or(f64::INFINITY);
+        match &mut self.value {
+            Var::Int(v) => *v = (*v as f64).clamp(min, max) as i64,
+            Var::Float(v) => *v = v.clamp(min, max),
+            _ => {}
+        }
+        self.reset_raw();
+    }
+
+    /// Sets the text shown in the `Enter` box from the current value.
+    pub fn reset_raw(&mut self) {
+        self.raw_value = match &self.value {
+            Var::String(s) => s.clone(),
+            value => raw_text(value),
+        };
+        self.parse_error = None;
+    }
+}
+
+/// Floats keep their decimal point (`1.0`, not `1`), so the text parses back
+/// to a Float and not to an Int.
+fn raw_text(value: &Var) -> String {
+    match value {
+        Var::Float(f) => format!("{f:?}"),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FieldDesc, FieldKind};
+    use crate::graph::{Type, Var};
+
+    fn field(data_type: Type, value: Var, kind: FieldKind) -> FieldDesc {
+        FieldDesc {
+            name: "value".to_string(),
+            data_type,
+            value,
+            raw_value: String::new(),
+            parse_error: None,
+            kind,
+            constraints: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = |data_type, value: &str, kind| {
+            field(data_type, Var::String(value.to_string()), kind)
+                .validate()
+                .is_ok()
+        };
+        let slider = FieldKind::Slider {
+            min: 0.0,
+            max: 1.0,
+            step: None,
+        };
+        assert!(field(Type::Float, Var::Float(0.5), slider.clone())
+            .validate()
+            .is_ok());
+        assert!(!valid(Type::String, "", slider));
+
+        let dropdown = FieldKind::Dropdown(vec!["a".to_string(), "b".to_string()]);
+        assert!(valid(Type::String, "b", dropdown.clone()));
+        assert!(!valid(Type::String, "c", dropdown));
+
+        assert!(valid(Type::String, "#ff8800", FieldKind::Color));
+        assert!(!valid(Type::String, "orange", FieldKind::Color));
+    }
+
+    #[test]
+    fn test_parse_raw() {
+        let mut float = field(Type::Float, Var::Float(1.0), FieldKind::Enter);
+        float.raw_value = "2.5".to_string();
+        float.parse_raw();
+        assert_eq!(float.value, Var::Float(2.5));
+        assert!(float.error().is_none());
+
+        float.raw_value = "abc".to_string();
+        float.parse_raw();
+        assert_eq!(float.value, Var::Float(2.5));
+        assert!(float.error().is_some());
+
+        float.raw_value = String::new();
+        float.parse_raw();
+        assert_eq!(float.error().as_deref(), Some("A value is required"));
+
+        let mut multi = field(
+            Type::Multi(vec![Type::Int, Type::Float, Type::String]),
+            Var::Float(1.5),
+            FieldKind::Enter,
+        );
+        multi.raw_value = "0.0".to_string();
+        multi.parse_raw();
+        assert_eq!(multi.value, Var::Float(0.0));
+        assert_eq!(multi.raw_value, "0.0");
+        multi.raw_value = "3".to_string();
+        multi.parse_raw();
+        assert_eq!(multi.value, Var::Int(3));
+        multi.value = Var::String("a".to_string());
+        multi.raw_value = "1".to_string();
+        multi.parse_raw();
+        assert_eq!(multi.value, Var::Int(1));
+
+        let mut string = field(Type::String, Var::String("a".to_string()), FieldKind::Enter);
+        string.raw_value = String::new();
+        string.parse_raw();
+        assert_eq!(string.value, Var::String(String::new()));
+        assert!(string.error().is_none());
+    }
+
+    #[test]
+    fn test_constraints() {
+        let mut int = field(Type::Int, Var::Int(5), FieldKind::Enter);
+        int.constraints.min = Some(0.0);
+        int.constraints.max = Some(3.0);
+        assert!(int.error().is_some());
+        int.value = Var::Int(3);
+        assert!(int.error().is_none());
+
+        let mut name = field(Type::String, Var::String(String::new()), FieldKind::Enter);
+        name.constraints.non_empty = true;
+        name.constraints.pattern = Some("^[a-z_]+$".to_string());
+        assert_eq!(name.error().as_deref(), Some("Must not be empty"));
+        name.value = Var::String("Bad Name".to_string());
+        assert!(name.error().is_some());
+        name.value = Var::String("good_name".to_string());
+        assert!(name.error().is_none());
+    }
+
+    #[test]
+    fn test_duplicate_key() {
+        let entry = |key: &str| (Var::String(key.to_string()), Var::Int(0));
+        let mut map = field(
+            Type::Map(Box::new(Type::String), Box::new(Type::Int)),
+            Var::Map(Type::String, Type::Int, vec![entry("a"), entry("b")]),
+            FieldKind::Enter,
+        );
+        assert!(map.error().is_none());
+        if let Var::Map(_, _, entries) = &mut map.value {
+            entries[1].0 = Var::String("a".to_string());
+        }
+        assert_eq!(
+            map.error().as_deref(),
+            Some("Key \"a\" is used more than once")
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut slider = field(
+            Type::Int,
+            Var::Int(20),
+            FieldKind::Slider {
+                min: 0.0,
+                max: 10.0,
+                step: Some(1.0),
+            },
+        );
+        slider.normalize();
+        assert_eq!(slider.value, Var::Int(10));
+
+        let mut dropdown = field(
+            Type::String,
+            Var::String("gone".to_string()),
+            FieldKind::Dropdown(vec!["a".to_string()]),
+        );
+        dropdown.normalize();
+        assert_eq!(dropdown.value, Var::String("a".to_string()));
+    }
+
+    #[test]
+    fn test_kind_yaml() {
+        let yaml = r#"
+name: "mode"
+type: !String
+value: !String "fast"
+kind: !Dropdown ["fast", "slow"]
+"#;
+        let field: FieldDesc = serde_yaml_ng::from_str(yaml).unwrap();
+        assert!(matches!(field.kind, FieldKind::Dropdown(ref c) if c.len() == 2));
+
+        let yaml = "!Slider { min: 0.0, max: 2.0 }";
+        let kind: FieldKind = serde_yaml_ng::from_str(yaml).unwrap();
+        assert!(matches!(kind, FieldKind::Slider { step: None, .. }));
+    }
 }
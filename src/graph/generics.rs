@@ -0,0 +1,270 @@
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+
+use super::{custom, Coercion, CustomType, NodeDesc, Type};
+
+/// Type variables declared by a node descriptor with the types each one may be
+/// bound to, e.g. `T: [Int, Float]`. An empty list allows any type.
+pub type Generics = BTreeMap<String, Vec<Type>>;
+
+/// Concrete types the type variables of one node are bound to.
+pub type Bindings = BTreeMap<String, Type>;
+
+pub fn contains_generic(data_type: &Type) -> bool {
+    match data_type {
+        Type::Generic(_) => true,
+        Type::List(t) | Type::Optional(t) => contains_generic(t),
+        Type::Map(k, v) => contains_generic(k) || contains_generic(v),
+        Type::Multi(types) => types.iter().any(contains_generic),
+        _ => false,
+    }
+}
+
+/// Whether `data_type` is a concrete type a type variable can be bound to.
+fn is_concrete(data_type: &Type) -> bool {
+    match data_type {
+        Type::Multi(_) | Type::Inherit(_) | Type::Generic(_) => false,
+        Type::List(t) | Type::Optional(t) => is_concrete(t),
+        Type::Map(k, v) => is_concrete(k) && is_concrete(v),
+        _ => true,
+    }
+}
+
+/// Matches `pattern` against the type `actual` and binds the type variables it
+/// contains. Fails if a variable is already bound to a type `actual` cannot be
+/// widened to, or `actual` is not one of the types the variable is constrained to.
+/// Types that are not concrete yet (e.g. an unresolved `Multi`) are skipped.
+pub fn unify(
+    pattern: &Type,
+    actual: &Type,
+    generics: &Generics,
+    bindings: &mut Bindings,
+    types: &[CustomType],
+) -> Result<()> {
+    match (pattern, actual) {
+        (Type::Generic(name), _) => {
+            let Some(constraint) = generics.get(name) else {
+                bail!("Type variable {} is not declared", name);
+            };
+            if !is_concrete(actual) {
+                return Ok(());
+            }
+            if !constraint.is_empty()
+                && !constraint
+                    .iter()
+                    .any(|t| custom::compatible(t, actual, types))
+            {
+                bail!(
+                    "{} must be one of {}, got {}",
+                    name,
+                    Type::Multi(constraint.clone()),
+                    actual
+                );
+            }
+            match bindings.get(name) {
+                Some(bound)
+                    if !custom::compatible(bound, actual, types)
+                        && !Coercion::find(actual, bound).is_some_and(|c| c.is_widening()) =>
+                {
+                    bail!("{} is bound to {}, got {}", name, bound, actual)
+                }
+                Some(_) => {}
+                None => {
+                    bindings.insert(name.clone(), actual.clone());
+                }
+            }
+            Ok(())
+        }
+        (Type::List(p), Type::List(a)) | (Type::Optional(p), Type::Optional(a)) => {
+            unify(p, a, generics, bindings, types)
+        }
+        (Type::Optional(p), _) => unify(p, actual, generics, bindings, types),
+        (Type::Map(pk, pv), Type::Map(ak, av)) => {
+            unify(pk, ak, generics, bindings, types)?;
+            unify(pv, av, generics, bindings, types)
+        }
+        _ if contains_generic(pattern) && is_concrete(actual) => {
+            bail!("Expected {}, got {}", pattern, actual)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Binds the type variables of `desc` from the types connected to its inputs,
+/// given as `(input index, type)`, and from the values of its fields.
+pub fn bind(
+    desc: &NodeDesc,
+    input_types: impl IntoIterator<Item = (usize, Type)>,
+    types: &[CustomType],
+) -> Result<Bindings> {
+    let input_types: Vec<(usize, Type)> = input_types.into_iter().collect();
+    let mut bindings = Bindings::new();
+
+    // Bind variables used by several inputs to the type all of them widen to,
+    // so `Int + Float` binds `T = Float` regardless of the input order.
+    for name in desc.generics.keys() {
+        let candidates: Vec<&Type> = input_types
+            .iter()
+            .filter(|(index, actual)| {
+                is_concrete(actual)
+                    && desc
+                        .inputs
+                        .get(*index)
+                        .is_some_and(|i| matches!(&i.data_type, Type::Generic(n) if n == name))
+            })
+            .map(|(_, actual)| actual)
+            .collect();
+        let widest = candidates.iter().find(|candidate| {
+            candidates.iter().all(|other| {
+                other == *candidate
+                    || Coercion::find(other, candidate).is_some_and(|c| c.is_widening())
+            })
+        });
+        let constraint = &desc.generics[name];
+        if let Some(widest) = widest
+            && (constraint.is_empty()
+                || constraint
+                    .iter()
+                    .any(|t| custom::compatible(t, widest, types)))
+        {
+            bindings.insert(name.clone(), (*widest).clone());
+        }
+    }
+
+    for field in &desc.fields {
+        unify(
+            &field.data_type,
+            &field.value.type_(),
+            &desc.generics,
+            &mut bindings,
+            types,
+        )?;
+    }
+    for (index, actual) in input_types {
+        let Some(input) = desc.inputs.get(index) else {
+            continue;
+        };
+        if let Err(e) = unify(
+            &input.data_type,
+            &actual,
+            &desc.generics,
+            &mut bindings,
+            types,
+        ) {
+            bail!("Input {}: {}", input.name, e);
+        }
+    }
+    Ok(bindings)
+}
+
+/// Replaces the type variables in `data_type` with their bindings. Unbound
+/// variables become the types they are constrained to.
+pub fn resolve(data_type: &Type, generics: &Generics, bindings: &Bindings) -> Type {
+    match data_type {
+        Type::Generic(name) => match (bindings.get(name), generics.get(name)) {
+            (Some(bound), _) => bound.clone(),
+            (None, Some(constraint)) if constraint.len() == 1 => constraint[0].clone(),
+            (None, Some(constraint)) if !constraint.is_empty() => Type::Multi(constraint.clone()),
+            _ => data_type.clone(),
+        },
+        Type::List(t) => Type::List(Box::new(resolve(t, generics, bindings))),
+        Type::Optional(t) => Type::Optional(Box::new(resolve(t, generics, bindings))),
+        Type::Map(k, v) => Type::Map(
+            Box::new(resolve(k, generics, bindings)),
+            Box::new(resolve(v, generics, bindings)),
+        ),
+        Type::Multi(types) => Type::Multi(
+            types
+                .iter()
+                .map(|t| resolve(t, generics, bindings))
+                .collect(),
+        ),
+        _ => data_type.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bind, resolve, unify, Bindings, Generics};
+    use crate::graph::{DescStorage, Type};
+
+    fn numeric() -> Generics {
+        Generics::from([("T".to_string(), vec![Type::Int, Type::Float])])
+    }
+
+    #[test]
+    fn test_unify() {
+        let t = Type::Generic("T".to_string());
+        let mut bindings = Bindings::new();
+        unify(&t, &Type::Int, &numeric(), &mut bindings, &[]).unwrap();
+        assert_eq!(bindings.get("T"), Some(&Type::Int));
+        unify(&t, &Type::Int, &numeric(), &mut bindings, &[]).unwrap();
+        assert!(unify(&t, &Type::Float, &numeric(), &mut bindings, &[]).is_err());
+        bindings.insert("T".to_string(), Type::Float);
+        unify(&t, &Type::Int, &numeric(), &mut bindings, &[]).unwrap();
+
+        let mut bindings = Bindings::new();
+        assert!(unify(&t, &Type::String, &numeric(), &mut bindings, &[]).is_err());
+        unify(
+            &t,
+            &Type::Multi(vec![Type::Int, Type::Float]),
+            &numeric(),
+            &mut bindings,
+            &[],
+        )
+        .unwrap();
+        assert!(bindings.is_empty());
+
+        let list = Type::List(Box::new(t.clone()));
+        unify(
+            &list,
+            &Type::List(Box::new(Type::Float)),
+            &numeric(),
+            &mut bindings,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            resolve(&list, &numeric(), &bindings).to_string(),
+            "List(Float)"
+        );
+    }
+
+    #[test]
+    fn test_resolve_unbound() {
+        let t = Type::Generic("T".to_string());
+        assert_eq!(
+            resolve(&t, &numeric(), &Bindings::new()),
+            Type::Multi(vec![Type::Int, Type::Float])
+        );
+    }
+
+    #[test]
+    fn test_bind_math() {
+        let mut storage = DescStorage::new();
+        storage.import_std_libs().unwrap();
+        let add = storage.desc("Math", "Add").unwrap();
+
+        let bindings = bind(add, [(0, Type::Int), (1, Type::Int)], &[]).unwrap();
+        assert_eq!(
+            resolve(&add.outputs[0].data_type, &add.generics, &bindings),
+            Type::Int
+        );
+        let bindings = bind(add, [(0, Type::Int), (1, Type::Float)], &[]).unwrap();
+        assert_eq!(bindings.get("T"), Some(&Type::Float));
+        assert!(bind(add, [(0, Type::Int), (1, Type::String)], &[]).is_err());
+    }
+
+    #[test]
+    fn test_bind_unconstrained() {
+        let mut storage = DescStorage::new();
+        storage.import_std_libs().unwrap();
+        let select = storage.desc("Flow", "Select").unwrap();
+
+        for order in [[Type::Int, Type::Float], [Type::Float, Type::Int]] {
+            let [then, otherwise] = order;
+            let bindings = bind(select, [(1, then), (2, otherwise)], &[]).unwrap();
+            assert_eq!(bindings.get("T"), Some(&Type::Float));
+        }
+    }
+}
@@ -1,14 +1,25 @@
+mod coercion;
 mod connection;
+pub mod custom;
 mod desc_storage;
+mod document;
 mod field;
+pub mod generics;
 mod node;
 mod port;
+mod resync;
 mod var;
+pub mod variable;
 
+pub use coercion::Coercion;
 pub use connection::Connection;
-pub use desc_storage::DescStorage;
+pub use custom::CustomType;
+pub use desc_storage::{DescRef, DescStorage};
+pub use document::Document;
 pub use field::{FieldDesc, FieldKind};
-pub use node::{Node, NodeDesc};
+pub use node::{Node, NodeDesc, NodeRole};
 pub use port::PortDesc;
+pub use resync::{resync_node, NodeDiff};
 #[allow(unused)]
-pub use var::{CustomType, Type, Var};
+pub use var::{CustomValue, Type, Var};
+pub use variable::Variable;
@@ -1,7 +1,8 @@
 use eframe::egui::Pos2;
 use serde::{Deserialize, Serialize};
+use std::hash::{DefaultHasher, Hash, Hasher};
 
-use super::PortDesc;
+use super::{generics::Generics, DescRef, DescStorage, PortDesc, Var};
 use crate::graph::FieldDesc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -9,9 +10,13 @@ pub struct Node {
     pub id: usize,
     pub pos: (f32, f32),
     pub size: (f32, f32),
-    pub desc: NodeDesc,
+    #[serde(default = "DescRef::unknown")]
+    pub desc_ref: DescRef,
+    pub desc: NodeDesc, // Snapshot of the descriptor at `desc_ref.version`
     #[serde(skip)]
     pub stabilize_frames: usize,
+    #[serde(skip)]
+    desc_hash: Option<u64>, // See `desc_hash`, computed when the descriptor is set
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,11 +27,28 @@ pub struct NodeImpl {
     pub code: String,
 }
 
+/// Nodes the writer treats specially, besides emitting their code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeRole {
+    #[default]
+    Normal,
+    GraphInput,  // Becomes a command line option of the compiled program
+    GraphOutput, // Is part of the JSON printed when the program exits
+    GetVariable, // Reads its variable after the SetVariable nodes writing it
+    SetVariable,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeDesc {
     pub title: String,
     pub end: bool,
+    #[serde(default)]
+    pub role: NodeRole,
+    #[serde(default)]
+    pub pure: bool, // Output depends only on inputs and fields, no side effects
     pub desc: String,
+    #[serde(default)]
+    pub generics: Generics,
     pub fields: Vec<FieldDesc>,
     pub inputs: Vec<PortDesc>,
     pub outputs: Vec<PortDesc>,
@@ -34,6 +56,48 @@ pub struct NodeDesc {
 }
 
 impl Node {
+    /// A node at the origin with the descriptor `storage` provides for
+    /// `category` and `title`, its fields showing their defaults.
+    pub fn new(storage: &DescStorage, id: usize, category: &str, title: &str) -> Option<Self> {
+        let desc_ref = storage.desc_ref(category, title)?;
+        let mut desc = storage.resolve(&desc_ref)?.clone();
+        for field in &mut desc.fields {
+            field.reset_raw();
+        }
+        Some(Self::with_desc(id, desc_ref, desc))
+    }
+
+    /// A node at the origin with `desc`, sized to fit its ports.
+    pub fn with_desc(id: usize, desc_ref: DescRef, desc: NodeDesc) -> Self {
+        Self {
+            id,
+            pos: (0.0, 0.0),
+            size: (120.0, ports_height(&desc)),
+            desc_hash: Some(desc.structure_hash()),
+            desc_ref,
+            desc,
+            stabilize_frames: 0,
+        }
+    }
+
+    /// Replaces the descriptor, resizing the node to fit its ports.
+    pub fn set_desc(&mut self, desc_ref: DescRef, desc: NodeDesc) {
+        self.size.1 = ports_height(&desc);
+        self.desc_hash = Some(desc.structure_hash());
+        self.desc_ref = desc_ref;
+        self.desc = desc;
+    }
+
+    /// Computes the hash `desc_hash` returns, for nodes that were deserialized.
+    pub fn hash_desc(&mut self) {
+        self.desc_hash = Some(self.desc.structure_hash());
+    }
+
+    /// Hash of the descriptor without its field values.
+    pub fn desc_hash(&self) -> u64 {
+        self.desc_hash.unwrap_or_else(|| self.desc.structure_hash())
+    }
+
     pub fn port_pos(&self, port_index: usize, output: bool) -> Pos2 {
         let y = self.pos.1 + 35.0 + port_index as f32 * 22.0;
         let x = if output {
@@ -48,3 +112,20 @@ impl Node {
         self.desc.impls.iter().find(|ni| ni.lang == lang)
     }
 }
+
+impl NodeDesc {
+    /// Hash of everything but the field values, which the user edits.
+    pub fn structure_hash(&self) -> u64 {
+        let mut desc = self.clone();
+        for field in &mut desc.fields {
+            field.value = Var::Bool(false);
+        }
+        let mut hasher = DefaultHasher::new();
+        ron::to_string(&desc).unwrap_or_default().hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+fn ports_height(desc: &NodeDesc) -> f32 {
+    ((desc.inputs.len() + desc.outputs.len()) as f32) * 20.0
+}
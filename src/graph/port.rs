@@ -8,4 +8,6 @@ pub struct PortDesc {
     #[serde(rename = "type")]
     pub data_type: Type,
     pub default: Option<Var>,
+    #[serde(default)]
+    pub scope: Option<String>, // Flow output whose block the port belongs to, e.g. a loop body
 }
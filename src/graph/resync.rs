@@ -0,0 +1,227 @@
+use semver::Version;
+
+use super::{Connection, DescRef, DescStorage, Node, NodeDesc, PortDesc};
+
+/// Differences between the descriptor a node was created from and the one its
+/// library provides now.
+#[derive(Debug, Clone)]
+pub struct NodeDiff {
+    pub node_id: usize,
+    pub desc_ref: DescRef,
+    pub new_version: Option<Version>, // None if the library no longer provides the descriptor
+    pub removed_inputs: Vec<String>,
+    pub removed_outputs: Vec<String>,
+    pub removed_fields: Vec<String>,
+    pub added_inputs: Vec<String>,
+    pub added_outputs: Vec<String>,
+    pub added_fields: Vec<String>,
+}
+
+impl NodeDiff {
+    /// Returns `None` if the node is up to date with its library.
+    pub fn new(node: &Node, storage: &DescStorage) -> Option<Self> {
+        let Some(new_ref) = storage.desc_ref(&node.desc_ref.category, &node.desc_ref.title)
+        else {
+            return Some(Self {
+                node_id: node.id,
+                desc_ref: node.desc_ref.clone(),
+                new_version: None,
+                removed_inputs: port_names(&node.desc.inputs),
+                removed_outputs: port_names(&node.desc.outputs),
+                removed_fields: node.desc.fields.iter().map(|f| f.name.clone()).collect(),
+                added_inputs: Vec::new(),
+                added_outputs: Vec::new(),
+                added_fields: Vec::new(),
+            });
+        };
+        let new_desc = storage.resolve(&new_ref)?;
+
+        if new_ref.version == node.desc_ref.version && !desc_changed(&node.desc, new_desc) {
+            return None;
+        }
+
+        let old_fields: Vec<String> = node.desc.fields.iter().map(|f| f.name.clone()).collect();
+        let new_fields: Vec<String> = new_desc.fields.iter().map(|f| f.name.clone()).collect();
+        Some(Self {
+            node_id: node.id,
+            desc_ref: node.desc_ref.clone(),
+            new_version: Some(new_ref.version),
+            removed_inputs: missing(&port_names(&node.desc.inputs), &port_names(&new_desc.inputs)),
+            removed_outputs: missing(
+                &port_names(&node.desc.outputs),
+                &port_names(&new_desc.outputs),
+            ),
+            removed_fields: missing(&old_fields, &new_fields),
+            added_inputs: missing(&port_names(&new_desc.inputs), &port_names(&node.desc.inputs)),
+            added_outputs: missing(
+                &port_names(&new_desc.outputs),
+                &port_names(&node.desc.outputs),
+            ),
+            added_fields: missing(&new_fields, &old_fields),
+        })
+    }
+
+    pub fn is_removed(&self) -> bool {
+        self.new_version.is_none()
+    }
+
+    pub fn is_breaking(&self) -> bool {
+        self.is_removed()
+            || !self.removed_inputs.is_empty()
+            || !self.removed_outputs.is_empty()
+            || !self.removed_fields.is_empty()
+    }
+}
+
+/// Replaces the descriptor of `node`, keeping field values and connections
+/// whose names still exist. Returns a description of every dropped connection.
+pub fn resync_node(
+    node: &mut Node,
+    desc_ref: DescRef,
+    desc: &NodeDesc,
+    conns: &mut Vec<Connection>,
+) -> Vec<String> {
+    let mut dropped = Vec::new();
+
+    conns.retain_mut(|conn| {
+        if conn.to.0 == node.id {
+            let name = &node.desc.inputs[conn.to.1].name;
+            match desc.inputs.iter().position(|p| &p.name == name) {
+                Some(index) => conn.to.1 = index,
+                None => {
+                    dropped.push(format!(
+                        "{}#{} input '{}' (from node #{})",
+                        node.desc.title, node.id, name, conn.from.0
+                    ));
+                    return false;
+                }
+            }
+        }
+        if conn.from.0 == node.id {
+            let name = &node.desc.outputs[conn.from.1].name;
+            match desc.outputs.iter().position(|p| &p.name == name) {
+                Some(index) => conn.from.1 = index,
+                None => {
+                    dropped.push(format!(
+                        "{}#{} output '{}' (to node #{})",
+                        node.desc.title, node.id, name, conn.to.0
+                    ));
+                    return false;
+                }
+            }
+        }
+        true
+    });
+
+    let mut new_desc = desc.clone();
+    for field in &mut new_desc.fields {
+        field.reset_raw();
+        if let Some(old) = node.desc.fields.iter().find(|f| f.name == field.name)
+            && field.data_type.accepts(&old.value.type_())
+        {
+            field.value = old.value.clone();
+            field.raw_value = old.raw_value.clone();
+        }
+    }
+
+    node.set_desc(desc_ref, new_desc);
+
+    dropped
+}
+
+/// Compares two descriptors, ignoring the values the user entered into fields.
+fn desc_changed(a: &NodeDesc, b: &NodeDesc) -> bool {
+    let mut a = a.clone();
+    for field in &mut a.fields {
+        if let Some(other) = b.fields.iter().find(|f| f.name == field.name) {
+            field.value = other.value.clone();
+        }
+    }
+    match (ron::to_string(&a), ron::to_string(b)) {
+        (Ok(a), Ok(b)) => a != b,
+        _ => true,
+    }
+}
+
+fn port_names(ports: &[PortDesc]) -> Vec<String> {
+    ports.iter().map(|p| p.name.clone()).collect()
+}
+
+fn missing(from: &[String], to: &[String]) -> Vec<String> {
+    from.iter().filter(|n| !to.contains(n)).cloned().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{resync_node, NodeDiff};
+    use crate::graph::{Connection, DescStorage, Node, Var};
+
+    fn lib_yaml(version: &str, second_input: &str) -> String {
+        format!(
+            r#"category: "Test"
+lib: "Test"
+version: "{version}"
+nodes:
+  - title: "Node"
+    desc: ""
+    end: false
+    fields:
+      - name: "value"
+        type: !Int
+        value: !Int 0
+        kind: !Enter
+    inputs:
+      - name: "A"
+        type: !Int
+      - name: "{second_input}"
+        type: !Int
+    outputs:
+      - name: "result"
+        type: !Int
+    impls: []
+"#
+        )
+    }
+
+    #[test]
+    fn test_up_to_date() {
+        let mut storage = DescStorage::new();
+        storage.import(lib_yaml("1.0.0", "B"), false).unwrap();
+        let mut node = Node::new(&storage, 0, "Test", "Node").unwrap();
+        node.desc.fields[0].value = Var::Int(5);
+        assert!(NodeDiff::new(&node, &storage).is_none());
+    }
+
+    #[test]
+    fn test_resync() {
+        let mut storage = DescStorage::new();
+        storage.import(lib_yaml("1.0.0", "B"), false).unwrap();
+        let mut node = Node::new(&storage, 1, "Test", "Node").unwrap();
+        node.desc.fields[0].value = Var::Int(5);
+        let mut conns = vec![
+            Connection {
+                from: (0, 0),
+                to: (1, 0),
+            },
+            Connection {
+                from: (0, 0),
+                to: (1, 1),
+            },
+        ];
+
+        storage.import(lib_yaml("1.1.0", "C"), true).unwrap();
+        let diff = NodeDiff::new(&node, &storage).unwrap();
+        assert_eq!(diff.removed_inputs, vec!["B"]);
+        assert_eq!(diff.added_inputs, vec!["C"]);
+        assert!(diff.is_breaking());
+
+        let desc_ref = storage.desc_ref("Test", "Node").unwrap();
+        let desc = storage.resolve(&desc_ref).unwrap();
+        let dropped = resync_node(&mut node, desc_ref, desc, &mut conns);
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(conns.len(), 1);
+        assert_eq!(conns[0].to, (1, 0));
+        assert_eq!(node.desc.fields[0].value, Var::Int(5));
+        assert_eq!(node.desc_ref.version.to_string(), "1.1.0");
+    }
+}
@@ -1,6 +1,11 @@
 use serde::{Deserialize, Serialize};
 
-pub type CustomType = (String, String); // (type_name, value)
+/// A value of a library-declared custom type, with one value per declared field.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct CustomValue {
+    pub name: String,
+    pub fields: Vec<(String, Var)>,
+}
 
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub enum Type {
@@ -9,8 +14,50 @@ pub enum Type {
     Float,
     String,
     Custom(String),
+    List(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    Optional(Box<Type>),
     Multi(Vec<Type>),
     Inherit(String), // Special to be used in output of nodes
+    Generic(String), // Type variable declared in the node's `generics`
+    Flow,            // Execution order instead of a value, see `compiler::blocks`
+}
+
+impl Type {
+    /// Whether a value of type `other` can be stored where `self` is expected.
+    pub fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Flow, _) | (_, Type::Flow) => self == other,
+            (Type::Multi(types), _) => types.iter().any(|t| t.accepts(other)),
+            (Type::Inherit(_) | Type::Generic(_), _) => true,
+            (Type::List(a), Type::List(b)) => a.accepts(b),
+            (Type::Map(ka, va), Type::Map(kb, vb)) => ka.accepts(kb) && va.accepts(vb),
+            (Type::Optional(a), Type::Optional(b)) => a.accepts(b),
+            (Type::Optional(a), _) => a.accepts(other),
+            _ => self == other,
+        }
+    }
+
+    /// Whether values of this type can be used as dictionary keys.
+    pub fn is_hashable(&self) -> bool {
+        match self {
+            Type::Bool | Type::Int | Type::Float | Type::String => true,
+            Type::Optional(inner) => inner.is_hashable(),
+            Type::Multi(types) => types.iter().all(|t| t.is_hashable()),
+            _ => false,
+        }
+    }
+
+    /// Whether an input of type `self` can be connected to an output of type `output`.
+    /// Outputs with several possible types are compatible if any of them is accepted.
+    pub fn compatible_with(&self, output: &Type) -> bool {
+        match output {
+            _ if *self == Type::Flow || *output == Type::Flow => self == output,
+            Type::Multi(types) => types.iter().any(|t| self.accepts(t)),
+            Type::Inherit(_) | Type::Generic(_) => true,
+            _ => self.accepts(output),
+        }
+    }
 }
 
 impl std::fmt::Debug for Type {
@@ -21,11 +68,16 @@ impl std::fmt::Debug for Type {
             Type::Float => write!(f, "Float"),
             Type::String => write!(f, "String"),
             Type::Custom(s) => write!(f, "Custom({})", s),
+            Type::List(t) => write!(f, "List({:?})", t),
+            Type::Map(k, v) => write!(f, "Map({:?}, {:?})", k, v),
+            Type::Optional(t) => write!(f, "Optional({:?})", t),
             Type::Multi(types) => {
                 let types_str: Vec<String> = types.iter().map(|t| format!("{:?}", t)).collect();
                 write!(f, "Multi([{}])", types_str.join(", "))
             }
             Type::Inherit(s) => write!(f, "Inherit({})", s),
+            Type::Generic(s) => write!(f, "Generic({})", s),
+            Type::Flow => write!(f, "Flow"),
         }
     }
 }
@@ -38,11 +90,16 @@ impl std::fmt::Display for Type {
             Type::Float => write!(f, "Float"),
             Type::String => write!(f, "String"),
             Type::Custom(s) => write!(f, "Custom({})", s),
+            Type::List(t) => write!(f, "List({})", t),
+            Type::Map(k, v) => write!(f, "Map({}, {})", k, v),
+            Type::Optional(t) => write!(f, "Optional({})", t),
             Type::Multi(types) => {
                 let types_str: Vec<String> = types.iter().map(|t| t.to_string()).collect();
                 write!(f, "[{}]", types_str.join(", "))
             }
             Type::Inherit(s) => write!(f, "Inherit({})", s),
+            Type::Generic(s) => write!(f, "{}", s),
+            Type::Flow => write!(f, "Flow"),
         }
     }
 }
@@ -53,7 +110,10 @@ pub enum Var {
     Int(i64),
     Float(f64),
     String(String),
-    Custom(CustomType),
+    Custom(CustomValue),
+    List(Type, Vec<Var>),             // (element type, items)
+    Map(Type, Type, Vec<(Var, Var)>), // (key type, value type, entries)
+    Optional(Type, Option<Box<Var>>), // (inner type, value)
 }
 
 impl Var {
@@ -63,7 +123,44 @@ impl Var {
             Var::Int(_) => Type::Int,
             Var::Float(_) => Type::Float,
             Var::String(_) => Type::String,
-            Var::Custom((name, _)) => Type::Custom(name.clone()),
+            Var::Custom(c) => Type::Custom(c.name.clone()),
+            Var::List(t, _) => Type::List(Box::new(t.clone())),
+            Var::Map(k, v, _) => Type::Map(Box::new(k.clone()), Box::new(v.clone())),
+            Var::Optional(t, _) => Type::Optional(Box::new(t.clone())),
+        }
+    }
+
+    /// Whether every Float in the value is a finite number. JSON has no
+    /// NaN or infinity.
+    pub fn is_finite(&self) -> bool {
+        match self {
+            Var::Float(f) => f.is_finite(),
+            Var::Custom(c) => c.fields.iter().all(|(_, value)| value.is_finite()),
+            Var::List(_, items) => items.iter().all(Var::is_finite),
+            Var::Map(_, _, entries) => entries.iter().all(|(k, v)| k.is_finite() && v.is_finite()),
+            Var::Optional(_, value) => value.as_deref().is_none_or(Var::is_finite),
+            _ => true,
+        }
+    }
+
+    /// The first key that appears more than once in a map in the value. Only
+    /// one of the entries would survive compiling it to a dict.
+    pub fn duplicate_key(&self) -> Option<&Var> {
+        match self {
+            Var::Custom(c) => c.fields.iter().find_map(|(_, value)| value.duplicate_key()),
+            Var::List(_, items) => items.iter().find_map(Var::duplicate_key),
+            Var::Map(_, _, entries) => entries
+                .iter()
+                .enumerate()
+                .find(|(i, (key, _))| entries[..*i].iter().any(|(k, _)| k == key))
+                .map(|(_, (key, _))| key)
+                .or_else(|| {
+                    entries
+                        .iter()
+                        .find_map(|(k, v)| k.duplicate_key().or_else(|| v.duplicate_key()))
+                }),
+            Var::Optional(_, value) => value.as_deref().and_then(Var::duplicate_key),
+            _ => None,
         }
     }
 }
@@ -75,7 +172,27 @@ impl std::fmt::Debug for Var {
             Var::Int(i) => write!(f, "{}({})", self.type_(), i),
             Var::Float(fl) => write!(f, "{}({})", self.type_(), fl),
             Var::String(s) => write!(f, "{}(\"{}\")", self.type_(), s),
-            Var::Custom((name, value)) => write!(f, "{}({})", name, value),
+            Var::Custom(c) => {
+                let fields: Vec<String> = c
+                    .fields
+                    .iter()
+                    .map(|(name, value)| format!("{}: {:?}", name, value))
+                    .collect();
+                write!(f, "{} {{ {} }}", c.name, fields.join(", "))
+            }
+            Var::List(_, items) => {
+                let items: Vec<String> = items.iter().map(|i| format!("{:?}", i)).collect();
+                write!(f, "{}([{}])", self.type_(), items.join(", "))
+            }
+            Var::Map(_, _, entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(k, v)| format!("{:?}: {:?}", k, v))
+                    .collect();
+                write!(f, "{}({{{}}})", self.type_(), entries.join(", "))
+            }
+            Var::Optional(_, Some(value)) => write!(f, "{}({:?})", self.type_(), value),
+            Var::Optional(_, None) => write!(f, "{}(None)", self.type_()),
         }
     }
 }
@@ -87,7 +204,27 @@ impl std::fmt::Display for Var {
             Var::Int(i) => write!(f, "{}", i),
             Var::Float(fl) => write!(f, "{}", fl),
             Var::String(s) => write!(f, "\"{}\"", s),
-            Var::Custom(s) => write!(f, "({}: {})", s.1, s.0),
+            Var::Custom(c) => {
+                let fields: Vec<String> = c
+                    .fields
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value))
+                    .collect();
+                write!(f, "{} {{ {} }}", c.name, fields.join(", "))
+            }
+            Var::List(_, items) => {
+                let items: Vec<String> = items.iter().map(|i| i.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Var::Map(_, _, entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, v))
+                    .collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            Var::Optional(_, Some(value)) => write!(f, "{}", value),
+            Var::Optional(_, None) => write!(f, "None"),
         }
     }
 }
@@ -226,18 +363,12 @@ impl TryInto<f64> for Var {
     }
 }
 
-impl From<CustomType> for Var {
-    fn from(c: CustomType) -> Self {
+impl From<CustomValue> for Var {
+    fn from(c: CustomValue) -> Self {
         Var::Custom(c)
     }
 }
 
-impl From<(&str, &str)> for Var {
-    fn from(c: (&str, &str)) -> Self {
-        Var::from((c.0.to_string(), c.1.to_string()))
-    }
-}
-
 impl PartialEq for Var {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -245,9 +376,10 @@ impl PartialEq for Var {
             (Var::Int(a), Var::Int(b)) => a == b,
             (Var::Float(a), Var::Float(b)) => a == b,
             (Var::String(a), Var::String(b)) => a == b,
-            (Var::Custom((a_name, a_value)), Var::Custom((b_name, b_value))) => {
-                a_name == b_name && a_value == b_value
-            }
+            (Var::Custom(a), Var::Custom(b)) => a == b,
+            (Var::List(ta, a), Var::List(tb, b)) => ta == tb && a == b,
+            (Var::Map(ka, va, a), Var::Map(kb, vb, b)) => ka == kb && va == vb && a == b,
+            (Var::Optional(ta, a), Var::Optional(tb, b)) => ta == tb && a == b,
             _ => false,
         }
     }
@@ -255,7 +387,7 @@ impl PartialEq for Var {
 
 #[cfg(test)]
 mod test {
-    use super::{CustomType, Type, Var};
+    use super::{CustomValue, Type, Var};
 
     #[test]
     fn test_str() {
@@ -399,31 +531,82 @@ mod test {
         assert_ne!(Var::from(3.1f64), Var::from("3.1"));
     }
 
+    fn point(x: i64, y: i64) -> CustomValue {
+        CustomValue {
+            name: "Point".to_string(),
+            fields: vec![("x".to_string(), x.into()), ("y".to_string(), y.into())],
+        }
+    }
+
     #[test]
     fn test_custom() {
-        let custom: CustomType = ("Point".to_string(), "(1, 2)".to_string());
-        let var: Var = custom.into();
-        assert_eq!(var.to_string(), "((1, 2): Point)");
+        let var: Var = point(1, 2).into();
+        assert_eq!(var.to_string(), "Point { x: 1, y: 2 }");
         assert_eq!(var.type_().to_string(), "Custom(Point)");
-        assert_eq!(format!("{:?}", var), "Point((1, 2))");
-
-        let custom = ("Point", "(1, 2)");
-        let var: Var = custom.into();
-        assert_eq!(var.to_string(), "((1, 2): Point)");
-        assert_eq!(var.type_().to_string(), "Custom(Point)");
-        assert_eq!(format!("{:?}", var), "Point((1, 2))");
+        assert_eq!(format!("{:?}", var), "Point { x: Int(1), y: Int(2) }");
     }
 
     #[test]
     fn test_custom_eq() {
-        let custom1: CustomType = ("Point".to_string(), "(1, 2)".to_string());
-        let custom2: CustomType = ("Point".to_string(), "(1, 2)".to_string());
-        let custom3: CustomType = ("Point".to_string(), "(2, 3)".to_string());
-        let custom4: CustomType = ("Vector".to_string(), "(1, 2)".to_string());
-        assert_eq!(Var::from(custom1.clone()), Var::from(custom2));
-        assert_ne!(Var::from(custom1.clone()), Var::from(custom3));
-        assert_ne!(Var::from(custom1), Var::from(custom4));
-        assert_ne!(Var::from(("Point", "(1, 2)")), Var::from(0));
+        let mut vector = point(1, 2);
+        vector.name = "Vector".to_string();
+        assert_eq!(Var::from(point(1, 2)), Var::from(point(1, 2)));
+        assert_ne!(Var::from(point(1, 2)), Var::from(point(2, 3)));
+        assert_ne!(Var::from(point(1, 2)), Var::from(vector));
+        assert_ne!(Var::from(point(1, 2)), Var::from(0));
+    }
+
+    #[test]
+    fn test_list() {
+        let var = Var::List(Type::Float, vec![1.5.into(), 2.0.into()]);
+        assert_eq!(var.to_string(), "[1.5, 2]");
+        assert_eq!(var.type_().to_string(), "List(Float)");
+        assert_eq!(format!("{:?}", var), "List(Float)([Float(1.5), Float(2)])");
+        assert_ne!(var, Var::List(Type::Int, vec![]));
+    }
+
+    #[test]
+    fn test_map() {
+        let var = Var::Map(Type::String, Type::Int, vec![("a".into(), 1.into())]);
+        assert_eq!(var.to_string(), "{\"a\": 1}");
+        assert_eq!(var.type_().to_string(), "Map(String, Int)");
+    }
+
+    #[test]
+    fn test_optional() {
+        let some = Var::Optional(Type::Int, Some(Box::new(3.into())));
+        let none = Var::Optional(Type::Int, None);
+        assert_eq!(some.to_string(), "3");
+        assert_eq!(none.to_string(), "None");
+        assert_eq!(none.type_().to_string(), "Optional(Int)");
+        assert_ne!(some, none);
+    }
+
+    #[test]
+    fn test_container_accepts() {
+        let numbers = Type::List(Box::new(Type::Multi(vec![Type::Int, Type::Float])));
+        assert!(numbers.accepts(&Type::List(Box::new(Type::Int))));
+        assert!(!numbers.accepts(&Type::List(Box::new(Type::String))));
+        assert!(!numbers.accepts(&Type::Int));
+
+        let optional = Type::Optional(Box::new(Type::Float));
+        assert!(optional.accepts(&Type::Float));
+        assert!(optional.accepts(&Type::Optional(Box::new(Type::Float))));
+        assert!(!Type::Float.accepts(&optional));
+
+        let map = Type::Map(Box::new(Type::String), Box::new(Type::Int));
+        assert!(map.accepts(&Type::Map(Box::new(Type::String), Box::new(Type::Int))));
+        assert!(!map.accepts(&Type::Map(Box::new(Type::Int), Box::new(Type::Int))));
+    }
+
+    #[test]
+    fn test_container_yaml() {
+        let list: Type = serde_yaml_ng::from_str("!List Int").unwrap();
+        assert_eq!(list, Type::List(Box::new(Type::Int)));
+        let map: Type = serde_yaml_ng::from_str("!Map [String, Float]").unwrap();
+        assert_eq!(map.to_string(), "Map(String, Float)");
+        let value: Var = serde_yaml_ng::from_str("!List [Int, [!Int 1, !Int 2]]").unwrap();
+        assert_eq!(value.to_string(), "[1, 2]");
     }
 
     #[test]
@@ -435,4 +618,17 @@ mod test {
         ]);
         assert_eq!(multi_type.to_string(), "[Int, String, Custom(Point)]");
     }
+
+    #[test]
+    fn test_compatible_with() {
+        let numeric = Type::Multi(vec![Type::Int, Type::Float]);
+        assert!(numeric.compatible_with(&Type::Int));
+        assert!(!numeric.compatible_with(&Type::String));
+        assert!(Type::Float.compatible_with(&numeric));
+        assert!(!Type::Bool.compatible_with(&numeric));
+        assert!(Type::String.compatible_with(&Type::Inherit("ti_A".to_string())));
+        assert!(Type::Flow.compatible_with(&Type::Flow));
+        assert!(!Type::Flow.compatible_with(&Type::Inherit("ti_A".to_string())));
+        assert!(!Type::Inherit("ti_A".to_string()).compatible_with(&Type::Flow));
+    }
 }
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+use super::{Type, Var};
+
+/// A named value stored in the workspace and read or written by Get/Set nodes.
+/// Parameters become command line arguments of the generated program.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Variable {
+    pub name: String,
+    pub value: Var,
+    #[serde(default)]
+    pub parameter: bool,
+}
+
+impl Variable {
+    pub fn data_type(&self) -> Type {
+        self.value.type_()
+    }
+
+    /// Name of the Python variable holding the value in generated code.
+    pub fn ident(&self) -> String {
+        format!("var_{}", self.name)
+    }
+
+    /// Only primitive values can be passed on the command line.
+    pub fn can_be_parameter(&self) -> bool {
+        matches!(
+            self.value,
+            Var::Bool(_) | Var::Int(_) | Var::Float(_) | Var::String(_)
+        )
+    }
+}
+
+pub fn find<'a>(variables: &'a [Variable], name: &str) -> Option<&'a Variable> {
+    variables.iter().find(|v| v.name == name)
+}
+
+/// Whether `name` can be used as a variable name: an ASCII identifier.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_valid_name;
+
+    #[test]
+    fn test_valid_name() {
+        assert!(is_valid_name("speed"));
+        assert!(is_valid_name("_max_2"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("2fast"));
+        assert!(!is_valid_name("my speed"));
+    }
+}
@@ -1,7 +1,12 @@
 mod app;
 mod compiler;
+mod diff;
 mod file_picker;
 mod graph;
+mod libraries;
+mod palette;
+mod session;
+mod watch;
 mod workspace;
 
 pub use app::{App, Shared};
@@ -0,0 +1,221 @@
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{mpsc::Receiver, Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tracing::{debug, info, warn};
+
+pub const LIB_EXTENSION: &str = ".no3lib.yaml";
+pub const LOCAL_LIB_DIR: &str = ".no3libs";
+pub const LIB_PATH_ENV: &str = "NO3_LIB_PATH";
+const CONFIG_FILE: &str = "library_paths.yaml";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+type Listing = Vec<(PathBuf, SystemTime)>; // Libraries in the search directories with their modification times
+
+#[derive(Default, Serialize, Deserialize)]
+struct LibraryConfig {
+    dirs: Vec<PathBuf>,
+}
+
+/// Directories scanned for `*.no3lib.yaml` files, and the modification times of
+/// the files imported from them so changed libraries can be reloaded. The
+/// directories are watched on a background thread from startup.
+pub struct LibraryPaths {
+    pub extra_dirs: Vec<PathBuf>,
+    mtimes: HashMap<PathBuf, SystemTime>, // Imported libraries
+    failed: HashMap<PathBuf, SystemTime>, // Retried when they change or another library is imported
+    watched: Arc<Mutex<Vec<PathBuf>>>,
+    listings: Option<Receiver<Listing>>,
+}
+
+impl LibraryPaths {
+    pub fn new() -> Self {
+        let extra_dirs = match Self::load_config() {
+            Ok(config) => config.dirs,
+            Err(e) => {
+                debug!("No library path config loaded: {e:?}");
+                Vec::new()
+            }
+        };
+        let mut paths = Self {
+            extra_dirs,
+            mtimes: HashMap::new(),
+            failed: HashMap::new(),
+            watched: Arc::new(Mutex::new(Vec::new())),
+            listings: None,
+        };
+        *paths.watched.lock().unwrap() = paths.dirs();
+        paths.listings = Self::watch(paths.watched.clone());
+        paths
+    }
+
+    /// Sends a listing of the libraries in `dirs` every `POLL_INTERVAL`.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn watch(dirs: Arc<Mutex<Vec<PathBuf>>>) -> Option<Receiver<Listing>> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let spawned = std::thread::Builder::new()
+            .name("library watcher".to_string())
+            .spawn(move || {
+                loop {
+                    let Ok(dirs) = dirs.lock().map(|dirs| dirs.clone()) else {
+                        return;
+                    };
+                    if sender.send(Self::listing(&dirs)).is_err() {
+                        return; // The app is gone
+                    }
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+            });
+        match spawned {
+            Ok(_) => Some(receiver),
+            Err(e) => {
+                warn!("Failed to watch library paths: {e:?}");
+                None
+            }
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn watch(_dirs: Arc<Mutex<Vec<PathBuf>>>) -> Option<Receiver<Listing>> {
+        None // No file system to watch
+    }
+
+    fn project_dirs() -> Option<ProjectDirs> {
+        ProjectDirs::from("", "", "NodesO3")
+    }
+
+    fn config_path() -> Option<PathBuf> {
+        Self::project_dirs().map(|dirs| dirs.config_dir().join(CONFIG_FILE))
+    }
+
+    fn load_config() -> Result<LibraryConfig> {
+        let path = Self::config_path().context("No config directory available")?;
+        let yaml = std::fs::read_to_string(&path).context("Failed to read library path config")?;
+        serde_yaml_ng::from_str(&yaml).context("Failed to parse library path config")
+    }
+
+    pub fn save_config(&self) -> Result<()> {
+        let path = Self::config_path().context("No config directory available")?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).context("Failed to create config directory")?;
+        }
+        let config = LibraryConfig {
+            dirs: self.extra_dirs.clone(),
+        };
+        let yaml =
+            serde_yaml_ng::to_string(&config).context("Failed to serialize library path config")?;
+        std::fs::write(path, yaml).context("Failed to write library path config")
+    }
+
+    /// All directories searched for libraries, in load order: the user config
+    /// directory, the project-local folder, `NO3_LIB_PATH` and the configured extras.
+    pub fn dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        if let Some(project_dirs) = Self::project_dirs() {
+            dirs.push(project_dirs.config_dir().join("libs"));
+        }
+        dirs.push(PathBuf::from(LOCAL_LIB_DIR));
+        if let Some(env_paths) = std::env::var_os(LIB_PATH_ENV) {
+            dirs.extend(std::env::split_paths(&env_paths));
+        }
+        dirs.extend(self.extra_dirs.iter().cloned());
+        dirs
+    }
+
+    fn scan_dir(dir: &Path) -> Vec<PathBuf> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .file_name()
+                        .is_some_and(|name| name.to_string_lossy().ends_with(LIB_EXTENSION))
+            })
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    fn listing(dirs: &[PathBuf]) -> Listing {
+        dirs.iter()
+            .flat_map(|dir| Self::scan_dir(dir))
+            .filter_map(|path| {
+                let mtime = Self::mtime(&path)?;
+                Some((path, mtime))
+            })
+            .collect()
+    }
+
+    /// Finds every library in the search directories, and watches the
+    /// directories from now on.
+    pub fn scan(&mut self) -> Vec<PathBuf> {
+        let dirs = self.dirs();
+        let paths: Vec<PathBuf> = dirs.iter().flat_map(|dir| Self::scan_dir(dir)).collect();
+        if let Ok(mut watched) = self.watched.lock() {
+            *watched = dirs;
+        }
+        info!(count = paths.len(), "Found libraries in search paths");
+        paths
+    }
+
+    fn mtime(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Remembers the modification times of the libraries that were imported,
+    /// so they are only reloaded when they change.
+    pub fn record(&mut self, paths: &[PathBuf], failed: &[PathBuf]) {
+        if paths.len() > failed.len() {
+            self.failed.clear();
+        }
+        for path in paths {
+            let Some(mtime) = Self::mtime(path) else {
+                continue;
+            };
+            if failed.contains(path) {
+                self.mtimes.remove(path);
+                self.failed.insert(path.clone(), mtime);
+            } else {
+                self.mtimes.insert(path.clone(), mtime);
+            }
+        }
+    }
+
+    /// Returns the libraries that were added or modified since they were last
+    /// imported, by the latest listing of the watcher.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let Some(listing) = self
+            .listings
+            .as_ref()
+            .and_then(|listings| listings.try_iter().last())
+        else {
+            return Vec::new();
+        };
+
+        let mut changed = Vec::new();
+        for (path, mtime) in &listing {
+            if self.mtimes.get(path) != Some(mtime) && self.failed.get(path) != Some(mtime) {
+                debug!(?path, "Library changed on disk");
+                changed.push(path.clone());
+            }
+        }
+        self.mtimes.retain(|path, _| {
+            let exists = listing.iter().any(|(p, _)| p == path);
+            if !exists {
+                warn!(?path, "Library was removed from disk");
+            }
+            exists
+        });
+        self.failed
+            .retain(|path, _| listing.iter().any(|(p, _)| p == path));
+        changed
+    }
+}
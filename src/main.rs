@@ -8,13 +8,16 @@ fn main() -> Result<()> {
 
     init_tracing();
 
-    let app = App::new().context("Creating app")?;
+    let mut app = App::new().context("Creating app")?;
 
     let options = eframe::NativeOptions::default();
     eframe::run_native(
         "Node Graph",
         options,
-        Box::new(|_cc| Ok(Box::new(app) as Box<dyn eframe::App>)),
+        Box::new(|cc| {
+            app.restore_session(cc.storage);
+            Ok(Box::new(app) as Box<dyn eframe::App>)
+        }),
     )
     .map_err(|e| anyhow!(format!("{e:?}")))
     .context("Running eframe")?;
@@ -42,13 +45,16 @@ fn main() {
             .dyn_into::<eframe::web_sys::HtmlCanvasElement>()
             .expect("the_canvas_id was not a HtmlCanvasElement");
 
-        let app = App::new().expect("Failed to create app");
+        let mut app = App::new().expect("Failed to create app");
 
         let start_result = eframe::WebRunner::new()
             .start(
                 canvas,
                 web_options,
-                Box::new(|_| Ok(Box::new(app))),
+                Box::new(|cc| {
+                    app.restore_session(cc.storage);
+                    Ok(Box::new(app))
+                }),
             )
             .await;
 
@@ -0,0 +1,339 @@
+use eframe::egui::{self, Color32, Context, Key, Pos2, RichText, ScrollArea, TextEdit, Window};
+
+use crate::graph::{generics, DescStorage, NodeDesc, PortDesc, Type};
+
+const MAX_RECENT: usize = 8;
+const MAX_RESULTS: usize = 50;
+
+#[derive(Debug, Clone)]
+pub struct PaletteEntry {
+    pub category: String,
+    pub title: String,
+    pub score: i32,
+}
+
+/// Shift+A node palette: fuzzy-searches every descriptor in the loaded libraries.
+pub struct Palette {
+    pub pos: Option<Pos2>,
+    query: String,
+    selected: usize,
+    recent: Vec<(String, String)>, // (category, title), most recent first
+    focus_query: bool,
+    filter: Option<Type>, // Only list nodes with an input accepting this type
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        Self {
+            pos: None,
+            query: String::new(),
+            selected: 0,
+            recent: Vec::new(),
+            focus_query: false,
+            filter: None,
+        }
+    }
+
+    pub fn open(&mut self, pos: Pos2) {
+        self.pos = Some(pos);
+        self.query.clear();
+        self.selected = 0;
+        self.focus_query = true;
+        self.filter = None;
+    }
+
+    /// Opens the palette listing only nodes that can be connected to an output of `output_type`.
+    pub fn open_filtered(&mut self, pos: Pos2, output_type: Type) {
+        self.open(pos);
+        self.filter = Some(output_type);
+    }
+
+    pub fn close(&mut self) {
+        self.pos = None;
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.pos.is_some()
+    }
+
+    /// Recently picked nodes as (category, title), most recent first.
+    pub fn recent(&self) -> &[(String, String)] {
+        &self.recent
+    }
+
+    pub fn set_recent(&mut self, mut recent: Vec<(String, String)>) {
+        recent.truncate(MAX_RECENT);
+        self.recent = recent;
+    }
+
+    fn remember(&mut self, category: &str, title: &str) {
+        self.recent.retain(|(c, t)| !(c == category && t == title));
+        self.recent
+            .insert(0, (category.to_string(), title.to_string()));
+        self.recent.truncate(MAX_RECENT);
+    }
+
+    /// Scores every descriptor against `query`. An empty query lists recently
+    /// used nodes first, followed by everything else in library order.
+    pub fn search(&self, storage: &DescStorage, query: &str) -> Vec<PaletteEntry> {
+        let mut entries = Vec::new();
+        for lib in &storage.libs {
+            for desc in &lib.descs {
+                if let Some(filter) = &self.filter
+                    && first_compatible_input(desc, filter).is_none()
+                {
+                    continue;
+                }
+                let recent_bonus = self
+                    .recent
+                    .iter()
+                    .position(|(c, t)| c == &lib.category && t == &desc.title)
+                    .map(|i| (MAX_RECENT - i) as i32 * 10)
+                    .unwrap_or(0);
+                let score = if query.is_empty() {
+                    Some(recent_bonus)
+                } else {
+                    desc_score(query, &lib.category, desc).map(|s| s + recent_bonus)
+                };
+                if let Some(score) = score {
+                    entries.push(PaletteEntry {
+                        category: lib.category.clone(),
+                        title: desc.title.clone(),
+                        score,
+                    });
+                }
+            }
+        }
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.score));
+        entries.truncate(MAX_RESULTS);
+        entries
+    }
+
+    /// Draws the palette and returns the `(category, title)` the user picked.
+    pub fn show(&mut self, ctx: &Context, storage: &DescStorage) -> Option<(String, String)> {
+        let pos = self.pos?;
+        let entries = self.search(storage, &self.query);
+
+        let (up, down, enter, escape) = ctx.input(|i| {
+            (
+                i.key_pressed(Key::ArrowUp),
+                i.key_pressed(Key::ArrowDown),
+                i.key_pressed(Key::Enter),
+                i.key_pressed(Key::Escape),
+            )
+        });
+        if escape {
+            self.close();
+            return None;
+        }
+        if down && self.selected + 1 < entries.len() {
+            self.selected += 1;
+        }
+        if up && self.selected > 0 {
+            self.selected -= 1;
+        }
+        self.selected = self.selected.min(entries.len().saturating_sub(1));
+
+        let mut picked = None;
+        if enter && let Some(entry) = entries.get(self.selected) {
+            picked = Some((entry.category.clone(), entry.title.clone()));
+        }
+
+        let title = match &self.filter {
+            Some(filter) => format!("Add Node ({})", filter),
+            None => "Add Node".to_string(),
+        };
+        let mut open = true;
+        Window::new(title)
+            .id(egui::Id::new("node_palette"))
+            .open(&mut open)
+            .fixed_pos(pos)
+            .collapsible(false)
+            .resizable(false)
+            .frame(egui::Frame {
+                inner_margin: 4.0.into(),
+                corner_radius: 0.into(),
+                fill: Color32::from_hex("#202020").unwrap(),
+                stroke: egui::Stroke::new(1.0, Color32::from_gray(100)),
+                shadow: egui::epaint::Shadow::NONE,
+                ..Default::default()
+            })
+            .show(ctx, |ui| {
+                let response = ui.add(
+                    TextEdit::singleline(&mut self.query)
+                        .hint_text("Search nodes")
+                        .desired_width(260.0),
+                );
+                if self.focus_query {
+                    response.request_focus();
+                    self.focus_query = false;
+                }
+                if response.changed() {
+                    self.selected = 0;
+                }
+
+                ui.separator();
+                ScrollArea::vertical().max_height(240.0).show(ui, |ui| {
+                    if entries.is_empty() {
+                        ui.weak("No matching nodes");
+                    }
+                    for (i, entry) in entries.iter().enumerate() {
+                        let label = format!("{} › {}", entry.category, entry.title);
+                        let response = ui.selectable_label(i == self.selected, label);
+                        if i == self.selected && (up || down) {
+                            response.scroll_to_me(None);
+                        }
+                        if response.hovered() {
+                            self.selected = i;
+                        }
+                        if response.clicked() {
+                            picked = Some((entry.category.clone(), entry.title.clone()));
+                        }
+                    }
+                });
+
+                if let Some(entry) = entries.get(self.selected)
+                    && let Some(desc) = storage.desc(&entry.category, &entry.title)
+                {
+                    ui.separator();
+                    ui.label(RichText::new(&desc.title).strong());
+                    ui.label(&desc.desc);
+                    ui.monospace(signature(desc));
+                }
+            });
+
+        if !open {
+            self.close();
+        }
+        if let Some((category, title)) = &picked {
+            self.remember(category, title);
+            self.close();
+        }
+        picked
+    }
+}
+
+/// Index of the first input of `desc` that accepts an output of `output_type`.
+pub fn first_compatible_input(desc: &NodeDesc, output_type: &Type) -> Option<usize> {
+    desc.inputs.iter().enumerate().position(|(i, input)| {
+        input.data_type.compatible_with(output_type)
+            && generics::bind(desc, [(i, output_type.clone())], &[]).is_ok()
+    })
+}
+
+pub fn signature(desc: &NodeDesc) -> String {
+    fn ports(ports: &[PortDesc]) -> String {
+        ports
+            .iter()
+            .map(|p| format!("{}: {}", p.name, p.data_type))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+    let generics = desc
+        .generics
+        .iter()
+        .map(|(name, constraint)| {
+            if constraint.is_empty() {
+                name.clone()
+            } else {
+                format!("{}: {}", name, Type::Multi(constraint.clone()))
+            }
+        })
+        .collect::<Vec<_>>();
+    let generics = if generics.is_empty() {
+        String::new()
+    } else {
+        format!("<{}>", generics.join(", "))
+    };
+    format!(
+        "{}({}) -> ({})",
+        generics,
+        ports(&desc.inputs),
+        ports(&desc.outputs)
+    )
+}
+
+fn desc_score(query: &str, category: &str, desc: &NodeDesc) -> Option<i32> {
+    let title = fuzzy_score(query, &desc.title).map(|s| s * 3);
+    let full = fuzzy_score(query, &format!("{} {}", category, desc.title)).map(|s| s * 2);
+    let description = fuzzy_score(query, &desc.desc);
+    let ports = desc
+        .inputs
+        .iter()
+        .chain(desc.outputs.iter())
+        .filter_map(|p| fuzzy_score(query, &p.name))
+        .max();
+    [title, full, description, ports]
+        .into_iter()
+        .flatten()
+        .max()
+}
+
+/// Case-insensitive subsequence match. Consecutive characters and matches at
+/// the start of words score higher, skipped characters lower the score.
+pub fn fuzzy_score(pattern: &str, text: &str) -> Option<i32> {
+    let pattern: Vec<char> = pattern
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    let text: Vec<char> = text.chars().collect();
+
+    let mut score = 0;
+    let mut p = 0;
+    let mut last_match: Option<usize> = None;
+    for (i, c) in text.iter().enumerate() {
+        if p == pattern.len() {
+            break;
+        }
+        if c.to_lowercase().eq(pattern[p].to_lowercase()) {
+            score += 10;
+            let word_start = i == 0
+                || !text[i - 1].is_alphanumeric()
+                || (c.is_uppercase() && text[i - 1].is_lowercase());
+            if word_start {
+                score += 8;
+            }
+            match last_match {
+                Some(last) if last + 1 == i => score += 6,
+                Some(last) => score -= (i - last - 1).min(5) as i32,
+                None => score -= i.min(5) as i32,
+            }
+            last_match = Some(i);
+            p += 1;
+        }
+    }
+
+    if p == pattern.len() {
+        Some(score)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::fuzzy_score;
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert!(fuzzy_score("add", "Add").is_some());
+        assert!(fuzzy_score("mlt", "Multiply").is_some());
+        assert!(fuzzy_score("xyz", "Multiply").is_none());
+        assert_eq!(fuzzy_score("", "Anything"), Some(0));
+    }
+
+    #[test]
+    fn test_fuzzy_ranking() {
+        let prefix = fuzzy_score("pow", "Power").unwrap();
+        let scattered = fuzzy_score("pow", "Print output window").unwrap();
+        assert!(prefix > scattered);
+
+        let word_start = fuzzy_score("dp", "Debug Print").unwrap();
+        let middle = fuzzy_score("dp", "Add pi").unwrap();
+        assert!(word_start > middle);
+    }
+}
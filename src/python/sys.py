@@ -1,2 +1,13 @@
 def panic(msg: str) -> None:
     raise RuntimeError(f"PANIC: {msg}")
+
+
+_graph_outputs = {}
+
+
+def graph_input(name: str):
+    return getattr(_args, name)
+
+
+def graph_output(name: str, value) -> None:
+    _graph_outputs[name] = value
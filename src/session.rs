@@ -0,0 +1,75 @@
+use chrono::{DateTime, Utc};
+use eframe::Storage;
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, time::Duration};
+
+const KEY: &str = "session";
+const MAX_RECENT: usize = 10;
+pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What the app remembers between launches, kept in eframe's storage. eframe
+/// saves it every `AUTOSAVE_INTERVAL` and on exit, so a crash loses at most
+/// the changes made since the last snapshot.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub path: Option<PathBuf>, // File the open workspace was loaded from or saved to
+    pub recent: Vec<PathBuf>,  // Most recent first
+    pub snapshot: Option<Snapshot>,
+    pub recent_nodes: Vec<(String, String)>, // Picked in the palette, as (category, title)
+}
+
+/// A workspace with changes that were not saved to its file.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub path: Option<PathBuf>, // None for a workspace that was never saved
+    pub timestamp: DateTime<Utc>,
+    pub text: String, // The workspace as RON
+}
+
+impl Session {
+    pub fn load(storage: Option<&dyn Storage>) -> Self {
+        storage
+            .and_then(|storage| eframe::get_value(storage, KEY))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, storage: &mut dyn Storage) {
+        eframe::set_value(storage, KEY, self);
+    }
+
+    /// Moves `path` to the top of the recent files.
+    pub fn add_recent(&mut self, path: PathBuf) {
+        self.recent.retain(|p| p != &path);
+        self.recent.insert(0, path);
+        self.recent.truncate(MAX_RECENT);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{Session, MAX_RECENT};
+
+    #[test]
+    fn test_add_recent() {
+        let mut session = Session::default();
+        for i in 0..MAX_RECENT + 2 {
+            session.add_recent(PathBuf::from(format!("{i}.no3")));
+        }
+        session.add_recent(PathBuf::from("5.no3"));
+        assert_eq!(session.recent.len(), MAX_RECENT);
+        assert_eq!(session.recent[0], PathBuf::from("5.no3"));
+        assert_eq!(session.recent[1], PathBuf::from("11.no3"));
+        assert!(!session.recent.contains(&PathBuf::from("1.no3")));
+        assert_eq!(
+            session
+                .recent
+                .iter()
+                .filter(|p| p.ends_with("5.no3"))
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use eframe::egui::{Color32, Context, RichText, ScrollArea, SidePanel, TextEdit};
+use std::{
+    sync::mpsc::{Receiver, TryRecvError},
+    time::Duration,
+};
+use tracing::error;
+
+use crate::{
+    compiler::{Compilation, Compiler, NodeCache},
+    diff::{self, Change},
+};
+
+/// Seconds the workspace has to stay unchanged before it is recompiled.
+const DEBOUNCE: f64 = 0.5;
+
+type Outcome = (Result<Compilation, String>, NodeCache); // With the ops lowered, for the next compilation
+
+/// Compile on change: recompiles the workspace on a background thread once
+/// edits settle and shows the latest result in a side panel.
+pub struct Watch {
+    pub enabled: bool,
+    fingerprint: Option<u64>,
+    changed_at: Option<f64>,
+    running: Option<Receiver<Outcome>>,
+    latest: Option<Compilation>,
+    previous_code: String,
+    error: Option<String>, // Of the last attempt, shown above the older `latest`
+    show_diff: bool,
+}
+
+impl Watch {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            fingerprint: None,
+            changed_at: None,
+            running: None,
+            latest: None,
+            previous_code: String::new(),
+            error: None,
+            show_diff: false,
+        }
+    }
+
+    /// Whether to compile now. `fingerprint` identifies what compiling reads
+    /// and is only called while watching. True once it stopped changing for
+    /// `DEBOUNCE` seconds and the previous compilation finished.
+    pub fn poll(&mut self, ctx: &Context, fingerprint: impl FnOnce() -> u64) -> bool {
+        if !self.enabled {
+            self.fingerprint = None;
+            return false;
+        }
+        let time = ctx.input(|i| i.time);
+        let fingerprint = fingerprint();
+        if self.fingerprint != Some(fingerprint) {
+            self.fingerprint = Some(fingerprint);
+            self.changed_at = Some(time);
+        }
+        match self.changed_at {
+            Some(changed_at) if time - changed_at < DEBOUNCE => {
+                let remaining = DEBOUNCE - (time - changed_at);
+                ctx.request_repaint_after(Duration::from_secs_f64(remaining));
+                false
+            }
+            Some(_) if self.running.is_none() => {
+                self.changed_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Runs `compiler` on a background thread, or right away where there are
+    /// no threads. `receive` picks up the result.
+    pub fn start(&mut self, ctx: &Context, compiler: Compiler) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let ctx = ctx.clone();
+        let job = move || {
+            let _ = sender.send(Self::run(compiler));
+            ctx.request_repaint();
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Err(e) = std::thread::Builder::new()
+            .name("watch compiler".to_string())
+            .spawn(job)
+        {
+            error!("Failed to start compiling: {e:?}");
+            self.error = Some(format!("Failed to start compiling: {e:?}"));
+            return;
+        }
+        #[cfg(target_arch = "wasm32")]
+        job();
+        self.running = Some(receiver);
+    }
+
+    fn run(mut compiler: Compiler) -> Outcome {
+        let result = compiler.compile().map_err(|e| {
+            error!("Compilation failed: {e:?}");
+            format!("Compilation failed: {e:?}")
+        });
+        (result, compiler.into_cache())
+    }
+
+    /// Shows the result of a finished compilation. Returns the ops it
+    /// lowered, for the next one.
+    pub fn receive(&mut self) -> Option<NodeCache> {
+        let (result, cache) = match self.running.as_ref()?.try_recv() {
+            Ok(outcome) => outcome,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => {
+                self.running = None;
+                self.error = Some("Compilation stopped unexpectedly".to_string());
+                return None;
+            }
+        };
+        self.running = None;
+        self.set_result(result);
+        Some(cache)
+    }
+
+    pub fn set_result(&mut self, result: Result<Compilation, String>) {
+        match result {
+            Ok(compilation) => {
+                if let Some(latest) = self.latest.take() {
+                    self.previous_code = latest.code;
+                }
+                self.latest = Some(compilation);
+                self.error = None;
+            }
+            Err(error) => self.error = Some(error),
+        }
+    }
+
+    pub fn show(&mut self, ctx: &Context) {
+        if !self.enabled {
+            return;
+        }
+        SidePanel::right("watch_panel")
+            .resizable(true)
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading("Output");
+                    ui.selectable_value(&mut self.show_diff, false, "Code");
+                    ui.selectable_value(&mut self.show_diff, true, "Diff");
+                });
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::LIGHT_RED, error);
+                }
+                let Some(latest) = &self.latest else {
+                    if self.error.is_none() {
+                        ui.weak("Compiling…");
+                    }
+                    return;
+                };
+                ui.horizontal_wrapped(|ui| {
+                    for (stage, duration) in &latest.elapsed_times {
+                        ui.weak(format!("{} {:?}", stage, duration));
+                    }
+                });
+                for warning in &latest.warnings {
+                    ui.colored_label(Color32::YELLOW, format!("⚠ {}", warning));
+                }
+                ui.separator();
+
+                ScrollArea::both().auto_shrink(false).show(ui, |ui| {
+                    if !self.show_diff {
+                        ui.add(
+                            TextEdit::multiline(&mut latest.code.as_str())
+                                .code_editor()
+                                .desired_width(f32::INFINITY),
+                        );
+                        return;
+                    }
+                    for change in diff::lines(&self.previous_code, &latest.code) {
+                        let (prefix, line, color) = match change {
+                            Change::Same(line) => (' ', line, Color32::GRAY),
+                            Change::Added(line) => ('+', line, Color32::from_rgb(109, 183, 120)),
+                            Change::Removed(line) => ('-', line, Color32::from_rgb(214, 96, 96)),
+                        };
+                        ui.label(
+                            RichText::new(format!("{} {}", prefix, line))
+                                .monospace()
+                                .color(color),
+                        );
+                    }
+                });
+            });
+    }
+}
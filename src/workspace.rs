@@ -1,17 +1,40 @@
 use anyhow::{bail, Context as AnyhowContext, Result};
 use eframe::egui::{
-    Color32, Context, Frame, Id, LayerId, Order, Pos2, Shadow, Stroke, TextEdit, Ui, Window,
+    Align2, Button, Checkbox, Color32, ComboBox, Context, DragValue, FontId, Frame, Grid, Id,
+    LayerId, Order, Pos2, Shadow, Shape, Slider, Stroke, StrokeKind, TextEdit, Ui, Vec2, Window,
 };
+use egui_code_editor::{CodeEditor, ColorTheme, Syntax};
+use ron::ser::PrettyConfig;
 use serde::{Deserialize, Serialize};
-use std::{cell::RefCell, path::PathBuf, rc::Rc};
-use tracing::error;
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tracing::{error, info, warn};
 
 use crate::{
-    compiler::Compilation,
-    graph::{Connection, DescStorage, FieldDesc, FieldKind, Node, Type, Var},
+    compiler::{Compilation, DiagnosticKind, NodeCache},
+    file_picker::{DialogPurpose, FilePicker},
+    graph::{
+        custom, generics, resync_node, variable, Coercion, Connection, CustomType, DescRef,
+        DescStorage, Document, FieldDesc, FieldKind, Node, NodeDiff, Type, Var, Variable,
+    },
     Shared,
 };
 
+const FLOW_COLOR: Color32 = Color32::from_rgb(220, 220, 220);
+const STEP_COLOR: Color32 = Color32::from_rgb(230, 170, 60);
+
+/// Source of `Workspace::revision`, shared so a new workspace never starts at
+/// a revision an earlier one had.
+static REVISIONS: AtomicU64 = AtomicU64::new(0);
+
+fn next_revision() -> u64 {
+    REVISIONS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct WorkspaceData {
     pub version: String,
@@ -19,12 +42,51 @@ pub struct WorkspaceData {
     pub desc_storage: DescStorage,
     pub connections: Vec<Connection>,
     pub compilation: Option<Compilation>,
+    #[serde(default)]
+    pub variables: Vec<Variable>,
+}
+
+/// How a workspace file is encoded, by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkspaceFormat {
+    Ron,           // .no3, and files with other extensions
+    CompressedRon, // .no3zstd
+    Yaml,          // .no3.yaml
+    Json,          // .no3.json, see docs/workspace.schema.json
+}
+
+impl WorkspaceFormat {
+    pub const EXTENSIONS: &[&str] = &[".no3", ".no3zstd", ".no3.yaml", ".no3.json"];
+
+    pub fn from_path(path: &Path) -> Self {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if name.ends_with(".no3zstd") {
+            Self::CompressedRon
+        } else if name.ends_with(".no3.yaml") {
+            Self::Yaml
+        } else if name.ends_with(".no3.json") {
+            Self::Json
+        } else {
+            Self::Ron
+        }
+    }
 }
 
 pub struct Workspace {
     pub data: WorkspaceData,
     pub shared: Rc<RefCell<Shared>>,
     pub dragging_connection: Option<(usize, usize, Pos2)>, // (node_id, port_index, current_pos)>,
+    pub pending_upgrade: Option<Vec<NodeDiff>>,
+    field_picker: Option<((usize, usize), FilePicker)>, // ((node_id, field_index), picker)
+    pub show_variables: bool,
+    pub compile_cache: NodeCache, // Ops lowered by the last compilation
+    pub execution_order: Vec<usize>, // Numbered on the canvas while the compiler debugger is open
+    revision: u64,                // Bumped by every edit to the graph, its variables or libraries
+    connection_colors: Option<(u64, Vec<Color32>)>, // Colors of the connections at a revision
+    name_draft: Option<(usize, String)>, // (variable_index, name) being typed, applied on focus loss
 }
 
 impl Workspace {
@@ -36,24 +98,75 @@ impl Workspace {
                 desc_storage: DescStorage::new(),
                 connections: Vec::new(),
                 compilation: None,
+                variables: Vec::new(),
             },
             shared,
             dragging_connection: None,
+            pending_upgrade: None,
+            field_picker: None,
+            show_variables: false,
+            compile_cache: NodeCache::default(),
+            execution_order: Vec::new(),
+            revision: next_revision(),
+            connection_colors: None,
+            name_draft: None,
         }
     }
 
-    #[tracing::instrument(skip(shared))]
-    pub fn load(shared: Rc<RefCell<Shared>>, path: PathBuf, compressed: bool) -> Result<Self> {
-        let mut ron = std::fs::read(path).context("Failed to read workspace.")?;
-        if compressed {
-            ron = Self::decompress(&ron)
-                .context("Failed to decompress workspace")?
-                .as_bytes()
-                .to_vec();
-        }
-        let string_ron = String::from_utf8(ron).context("Failed to convert workspace to string")?;
-        let mut data: WorkspaceData =
-            ron::from_str(&string_ron).context("Failed to deserialize workspace")?;
+    /// Loads a saved workspace, with the std libraries, `search_libs` and the
+    /// other libraries it refers to.
+    #[tracing::instrument(skip(shared, search_libs))]
+    pub fn load(
+        shared: Rc<RefCell<Shared>>,
+        path: PathBuf,
+        search_libs: Vec<PathBuf>,
+    ) -> Result<Self> {
+        let format = WorkspaceFormat::from_path(&path);
+        let bytes = std::fs::read(&path).context("Failed to read workspace.")?;
+        let text = match format {
+            WorkspaceFormat::CompressedRon => {
+                Self::decompress(&bytes).context("Failed to decompress workspace")?
+            }
+            _ => String::from_utf8(bytes).context("Failed to convert workspace to string")?,
+        };
+        Self::from_text(shared, &text, format, &path, search_libs)
+    }
+
+    /// Loads a workspace from `text`, as if it was read from `path`. Library
+    /// paths are relative to it.
+    pub fn from_text(
+        shared: Rc<RefCell<Shared>>,
+        text: &str,
+        format: WorkspaceFormat,
+        path: &Path,
+        search_libs: Vec<PathBuf>,
+    ) -> Result<Self> {
+        let document = match format {
+            WorkspaceFormat::Ron | WorkspaceFormat::CompressedRon => {
+                ron::from_str::<Document>(text).map_err(anyhow::Error::from)
+            }
+            WorkspaceFormat::Yaml => {
+                serde_yaml_ng::from_str::<Document>(text).map_err(anyhow::Error::from)
+            }
+            WorkspaceFormat::Json => {
+                serde_json::from_str::<Document>(text).map_err(anyhow::Error::from)
+            }
+        };
+        let (mut data, search_libs) = match document {
+            Ok(document) => (
+                Self::from_document(document, path, search_libs, &shared)?,
+                Vec::new(),
+            ),
+            // Workspaces saved before documents embedded their libraries and
+            // descriptors, newer versions are imported over them
+            Err(e) if format != WorkspaceFormat::Yaml && format != WorkspaceFormat::Json => (
+                ron::from_str::<WorkspaceData>(text)
+                    .map_err(|_| e)
+                    .context("Failed to deserialize workspace")?,
+                search_libs,
+            ),
+            Err(e) => return Err(e.context("Failed to deserialize workspace")),
+        };
 
         if data.version != env!("CARGO_PKG_VERSION") {
             error!(
@@ -69,26 +182,127 @@ impl Workspace {
         }
 
         for node in &mut data.nodes {
+            if node.desc_ref.is_unknown() {
+                node.desc_ref = match data.desc_storage.find_title(&node.desc) {
+                    Some(desc_ref) => desc_ref,
+                    None => {
+                        warn!(
+                            "{}#{}: no library provides the descriptor",
+                            node.desc.title, node.id
+                        );
+                        DescRef {
+                            title: node.desc.title.clone(),
+                            ..DescRef::unknown()
+                        }
+                    }
+                };
+            }
             for field in &mut node.desc.fields {
-                field.raw_value = field.value.to_string();
+                field.normalize();
             }
+            node.hash_desc();
         }
 
-        Ok(Self {
+        let mut workspace = Self {
             data,
             shared,
             dragging_connection: None,
+            pending_upgrade: None,
+            field_picker: None,
+            show_variables: false,
+            compile_cache: NodeCache::default(),
+            execution_order: Vec::new(),
+            revision: next_revision(),
+            connection_colors: None,
+            name_draft: None,
+        };
+        if !search_libs.is_empty() {
+            workspace.import_libs(search_libs);
+        }
+        Ok(workspace)
+    }
+
+    /// Loads the std libraries, `search_libs` and the other libraries
+    /// `document` refers to, then resolves its nodes.
+    fn from_document(
+        document: Document,
+        path: &Path,
+        search_libs: Vec<PathBuf>,
+        shared: &Rc<RefCell<Shared>>,
+    ) -> Result<WorkspaceData> {
+        let mut desc_storage = DescStorage::new();
+        desc_storage.import_std_libs()?;
+        report_failed_libs(shared, &desc_storage.load_import_each(search_libs, true));
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for library in &document.libraries {
+            if let Some(lib_path) = &library.path
+                && !desc_storage.lib_exists(&library.category)
+                && let Err(e) = desc_storage.load_import(dir.join(lib_path), true)
+            {
+                warn!("Failed to import '{}': {e:?}", library.category);
+            }
+        }
+
+        let version = document.version.clone();
+        let variables = document.variables.clone();
+        let (nodes, connections, changes) = document.into_graph(&desc_storage)?;
+        report_load_changes(shared, &changes);
+        Ok(WorkspaceData {
+            version,
+            nodes,
+            desc_storage,
+            connections,
+            compilation: None,
+            variables,
         })
     }
 
+    fn document(&self, dir: &Path) -> Document {
+        Document::new(
+            &self.data.nodes,
+            &self.data.connections,
+            &self.data.variables,
+            &self.data.desc_storage,
+            dir,
+            &self.shared.borrow().library_dirs,
+        )
+    }
+
+    /// The workspace as saved to `dir`, before compression.
+    pub fn to_text(&self, format: WorkspaceFormat, dir: &Path) -> Result<String> {
+        let document = self.document(dir);
+        let mut text = match format {
+            WorkspaceFormat::Ron | WorkspaceFormat::CompressedRon => {
+                ron::ser::to_string_pretty(&document, PrettyConfig::default().depth_limit(3))?
+            }
+            WorkspaceFormat::Yaml => serde_yaml_ng::to_string(&document)?,
+            WorkspaceFormat::Json => {
+                if let Some(place) = document.non_finite() {
+                    bail!(
+                        "{place} is not a finite number, which JSON cannot store. \
+                         Save as .no3 or .no3.yaml instead."
+                    );
+                }
+                serde_json::to_string_pretty(&document)?
+            }
+        };
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        Ok(text)
+    }
+
     #[tracing::instrument(skip(self))]
-    pub fn save(&self, path: PathBuf, compress: bool) -> Result<()> {
-        let ron = ron::to_string(&self.data).context("Failed to serialize workspace")?;
-        if compress {
-            let compressed_data = Self::compress(ron).context("Failed to compress workspace")?;
+    pub fn save(&self, path: PathBuf) -> Result<()> {
+        let format = WorkspaceFormat::from_path(&path);
+        let text = self
+            .to_text(format, path.parent().unwrap_or(Path::new(".")))
+            .context("Failed to serialize workspace")?;
+        if format == WorkspaceFormat::CompressedRon {
+            let compressed_data = Self::compress(text).context("Failed to compress workspace")?;
             std::fs::write(path, compressed_data).context("Failed to write workspace.ron")?;
         } else {
-            std::fs::write(path, ron).context("Failed to write workspace.ron")?;
+            std::fs::write(path, text).context("Failed to write workspace.ron")?;
         }
         Ok(())
     }
@@ -110,46 +324,416 @@ impl Workspace {
         self.render_nodes(ctx);
         self.render_dragging_connection(ctx);
         self.render_compilation(ctx);
+        self.render_upgrade(ctx);
+        self.render_field_picker(ctx);
+        self.render_variables(ctx);
+    }
+
+    pub fn import_std_libs(&mut self) -> Result<()> {
+        self.data.desc_storage.import_std_libs()?;
+        self.touch();
+        self.check_upgrades();
+        Ok(())
+    }
+
+    /// Imports each of `paths`, showing an error for the ones that fail.
+    /// Returns the failed ones.
+    pub fn import_libs(&mut self, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        let failed = self.data.desc_storage.load_import_each(paths, true);
+        report_failed_libs(&self.shared, &failed);
+        self.touch();
+        self.check_upgrades();
+        failed.into_iter().map(|(path, _)| path).collect()
+    }
+
+    /// Changes with every edit to the graph, its variables and libraries.
+    /// Moving nodes does not count.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Marks the graph as edited, for edits made from outside the workspace.
+    pub fn touch(&mut self) {
+        self.revision = next_revision();
+    }
+
+    /// Collects the nodes whose descriptors changed since they were placed.
+    pub fn check_upgrades(&mut self) {
+        let diffs: Vec<NodeDiff> = self
+            .data
+            .nodes
+            .iter()
+            .filter_map(|node| NodeDiff::new(node, &self.data.desc_storage))
+            .collect();
+        if diffs.is_empty() {
+            self.pending_upgrade = None;
+        } else {
+            info!(count = diffs.len(), "Nodes affected by library upgrade");
+            self.pending_upgrade = Some(diffs);
+        }
+    }
+
+    pub fn apply_upgrade(&mut self) {
+        let Some(diffs) = self.pending_upgrade.take() else {
+            return;
+        };
+        for diff in diffs {
+            let Some(node) = self.data.nodes.iter_mut().find(|n| n.id == diff.node_id) else {
+                continue;
+            };
+            let Some(desc_ref) = self
+                .data
+                .desc_storage
+                .desc_ref(&diff.desc_ref.category, &diff.desc_ref.title)
+            else {
+                warn!(
+                    "{}#{} is no longer provided by '{}', keeping the old descriptor",
+                    diff.desc_ref.title, diff.node_id, diff.desc_ref.category
+                );
+                continue;
+            };
+            let Some(desc) = self.data.desc_storage.resolve(&desc_ref) else {
+                continue;
+            };
+            for dropped in resync_node(node, desc_ref, desc, &mut self.data.connections) {
+                warn!("Dropped connection to removed port: {}", dropped);
+            }
+        }
+        self.verify_connections();
+    }
+
+    fn render_upgrade(&mut self, ctx: &Context) {
+        let Some(diffs) = &self.pending_upgrade else {
+            return;
+        };
+
+        let mut apply = false;
+        let mut dismiss = false;
+        Window::new("Library Upgrade")
+            .collapsible(false)
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.label(format!("{} node(s) use a changed descriptor.", diffs.len()));
+                ui.separator();
+                Grid::new("upgrade_diff").striped(true).show(ui, |ui| {
+                    for diff in diffs {
+                        ui.label(format!("{}#{}", diff.desc_ref.title, diff.node_id));
+                        match &diff.new_version {
+                            Some(version) => {
+                                ui.label(format!("{} → {}", diff.desc_ref.version, version))
+                            }
+                            None => ui.colored_label(Color32::LIGHT_RED, "removed from library"),
+                        };
+                        ui.vertical(|ui| {
+                            for (label, names, color) in [
+                                ("- input", &diff.removed_inputs, Color32::LIGHT_RED),
+                                ("- output", &diff.removed_outputs, Color32::LIGHT_RED),
+                                ("- field", &diff.removed_fields, Color32::LIGHT_RED),
+                                ("+ input", &diff.added_inputs, Color32::LIGHT_GREEN),
+                                ("+ output", &diff.added_outputs, Color32::LIGHT_GREEN),
+                                ("+ field", &diff.added_fields, Color32::LIGHT_GREEN),
+                            ] {
+                                for name in names {
+                                    ui.colored_label(color, format!("{label} '{name}'"));
+                                }
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("Re-sync nodes").clicked() {
+                        apply = true;
+                    }
+                    if ui.button("Keep old descriptors").clicked() {
+                        dismiss = true;
+                    }
+                });
+            });
+
+        if apply {
+            self.apply_upgrade();
+        } else if dismiss {
+            self.pending_upgrade = None;
+        }
     }
 
     fn render_compilation(&mut self, ctx: &Context) {
-        if let Some(compilation) = &mut self.data.compilation {
-            compilation.update(ctx);
+        // Taken out so that exports can serialize the rest of the workspace
+        if let Some(mut compilation) = self.data.compilation.take() {
+            compilation.update(ctx, |dir| self.to_text(WorkspaceFormat::Ron, dir));
+            self.data.compilation = Some(compilation);
         }
     }
 
-    fn render_connections(&self, ctx: &Context) {
+    fn render_connections(&mut self, ctx: &Context) {
         let painter_bg = ctx.layer_painter(LayerId::background());
 
-        for conn in &self.data.connections {
+        if self
+            .connection_colors
+            .as_ref()
+            .is_none_or(|(revision, _)| *revision != self.revision)
+        {
+            self.connection_colors = Some((self.revision, self.connection_colors()));
+        }
+        let Some((_, colors)) = &self.connection_colors else {
+            return;
+        };
+        for (conn, color) in self.data.connections.iter().zip(colors) {
             let from = self.data.nodes[conn.from.0].port_pos(conn.from.1, true);
             let to = self.data.nodes[conn.to.0].port_pos(conn.to.1, false);
-            painter_bg.line_segment(
-                [from, to],
-                Stroke::new(2.0, Color32::from_rgb(109, 148, 197)),
-            );
+            painter_bg.line_segment([from, to], Stroke::new(2.0, *color));
         }
     }
 
+    /// Colors of the connections, by whether they carry flow and how their
+    /// values are coerced.
+    fn connection_colors(&self) -> Vec<Color32> {
+        self.data
+            .connections
+            .iter()
+            .map(|conn| {
+                let flow = self.output_type(conn.from.0, conn.from.1) == Some(Type::Flow);
+                match self.connection_coercion(conn.from, conn.to) {
+                    _ if flow => FLOW_COLOR,
+                    Some(coercion) if coercion.is_lossy() => Color32::from_rgb(214, 137, 66),
+                    Some(_) => Color32::from_rgb(109, 183, 160),
+                    None => Color32::from_rgb(109, 148, 197),
+                }
+            })
+            .collect()
+    }
+
     fn render_ports(&mut self, ctx: &Context) {
         let painter_fg = ctx.layer_painter(LayerId::new(Order::Background, Id::new("ports_layer")));
 
+        // Flow ports are triangles pointing in the direction of execution
+        let flow_port = |pos: Pos2| {
+            let points = vec![
+                pos + Vec2::new(-4.0, -6.0),
+                pos + Vec2::new(6.0, 0.0),
+                pos + Vec2::new(-4.0, 6.0),
+            ];
+            Shape::convex_polygon(points, FLOW_COLOR, Stroke::NONE)
+        };
+        let unused_outputs: Vec<(usize, usize)> = self
+            .data
+            .compilation
+            .iter()
+            .flat_map(|c| &c.diagnostics)
+            .filter_map(|d| match d.kind {
+                DiagnosticKind::UnusedOutput(port) => Some((d.node, port)),
+                _ => None,
+            })
+            .collect();
+        let excluded = self.excluded_nodes();
         for node in &self.data.nodes {
-            for i in 0..node.desc.inputs.len() {
+            for (i, input) in node.desc.inputs.iter().enumerate() {
                 let pos = node.port_pos(i, false);
-                painter_fg.circle_filled(pos, 5.0, Color32::from_rgb(179, 51, 51));
+                if input.data_type == Type::Flow {
+                    painter_fg.add(flow_port(pos));
+                } else {
+                    painter_fg.circle_filled(pos, 5.0, Color32::from_rgb(179, 51, 51));
+                }
             }
-            for i in 0..node.desc.outputs.len() {
+            for (i, output) in node.desc.outputs.iter().enumerate() {
                 let pos = node.port_pos(i, true);
-                painter_fg.circle_filled(pos, 5.0, Color32::from_rgb(51, 179, 51));
+                if output.data_type == Type::Flow {
+                    painter_fg.add(flow_port(pos));
+                } else if unused_outputs.contains(&(node.id, i)) || excluded.contains(&node.id) {
+                    painter_fg.circle_filled(pos, 5.0, Color32::from_gray(90));
+                } else {
+                    painter_fg.circle_filled(pos, 5.0, Color32::from_rgb(51, 179, 51));
+                }
             }
         }
     }
 
-    fn render_nodes(&mut self, ctx: &Context) {
+    /// Nodes the last compilation left out of the program.
+    fn excluded_nodes(&self) -> Vec<usize> {
+        self.data
+            .compilation
+            .iter()
+            .flat_map(|c| &c.diagnostics)
+            .filter(|d| d.is_excluded())
+            .map(|d| d.node)
+            .collect()
+    }
+
+    fn render_variables(&mut self, ctx: &Context) {
+        if !self.show_variables {
+            return;
+        }
+        let custom_types = self.data.desc_storage.custom_types();
+        let mut types = vec![Type::Bool, Type::Int, Type::Float, Type::String];
+        types.extend(custom_types.iter().map(|t| Type::Custom(t.name.clone())));
+
+        let mut open = true;
+        let mut rename = None;
+        let mut remove = None;
+        let mut add = false;
+        let mut changed = false;
+        Window::new("Variables")
+            .open(&mut open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                Grid::new("variables").striped(true).show(ui, |ui| {
+                    ui.strong("Name");
+                    ui.strong("Type");
+                    ui.strong("Value");
+                    ui.strong("Parameter");
+                    ui.end_row();
+
+                    for (i, variable) in self.data.variables.iter_mut().enumerate() {
+                        let mut name = match &self.name_draft {
+                            Some((index, draft)) if *index == i => draft.clone(),
+                            _ => variable.name.clone(),
+                        };
+                        let valid = variable::is_valid_name(&name);
+                        let mut name_edit = TextEdit::singleline(&mut name).desired_width(100.0);
+                        if !valid {
+                            name_edit = name_edit.text_color(Color32::LIGHT_RED);
+                        }
+                        let response = ui.add(name_edit);
+                        if response.changed() {
+                            self.name_draft = Some((i, name));
+                        }
+                        if response.lost_focus() {
+                            rename = self.name_draft.take_if(|(index, _)| *index == i);
+                        }
+
+                        let mut data_type = variable.data_type();
+                        ComboBox::from_id_salt(("variable_type", i))
+                            .selected_text(data_type.to_string())
+                            .show_ui(ui, |ui| {
+                                for t in &types {
+                                    ui.selectable_value(&mut data_type, t.clone(), t.to_string());
+                                }
+                            });
+                        if data_type != variable.data_type()
+                            && let Some(value) = custom::default_var(&data_type, &custom_types)
+                        {
+                            variable.value = value;
+                            variable.parameter &= variable.can_be_parameter();
+                            changed = true;
+                        }
+
+                        let value = variable.value.clone();
+                        Self::var_edit(
+                            ui,
+                            &format!("variable_{}", i),
+                            &mut variable.value,
+                            &custom_types,
+                        );
+                        changed |= variable.value != value;
+                        changed |= ui
+                            .add_enabled(
+                                variable.can_be_parameter(),
+                                Checkbox::without_text(&mut variable.parameter),
+                            )
+                            .on_hover_text("Read from a command line argument")
+                            .changed();
+                        if ui.small_button("✕").clicked() {
+                            remove = Some(i);
+                        }
+                        ui.end_row();
+                    }
+                });
+                if ui.button("Add Variable").clicked() {
+                    add = true;
+                }
+            });
+
+        if let Some((index, name)) = rename {
+            self.rename_variable(index, name);
+        }
+        if let Some(index) = remove {
+            self.data.variables.remove(index);
+            self.name_draft = None;
+            changed = true;
+        }
+        if add {
+            let name = (1..)
+                .map(|n| format!("value_{}", n))
+                .find(|name| variable::find(&self.data.variables, name).is_none())
+                .unwrap();
+            self.data.variables.push(Variable {
+                name,
+                value: Var::Float(0.0),
+                parameter: false,
+            });
+            changed = true;
+        }
+        if changed {
+            self.touch();
+        }
+        if !open {
+            self.show_variables = false;
+        }
+    }
+
+    /// Renames a variable and the Get/Set nodes referring to it. Invalid names
+    /// are refused, and so are names used by another variable, so references
+    /// to the two don't merge.
+    fn rename_variable(&mut self, index: usize, name: String) {
+        if !variable::is_valid_name(&name) || variable::find(&self.data.variables, &name).is_some()
+        {
+            return;
+        }
+        let old = std::mem::replace(&mut self.data.variables[index].name, name.clone());
+        self.touch();
+        if old.is_empty() {
+            return;
+        }
         for node in &mut self.data.nodes {
+            for field in &mut node.desc.fields {
+                if let FieldKind::Variable = field.kind
+                    && field.value == Var::String(old.clone())
+                {
+                    field.value = Var::String(name.clone());
+                    field.reset_raw();
+                }
+            }
+        }
+    }
+
+    fn render_field_picker(&mut self, ctx: &Context) {
+        let Some(((node_id, field_index), picker)) = &mut self.field_picker else {
+            return;
+        };
+        picker.show(ctx);
+        let Some(path) = picker.picked_path.clone() else {
+            return;
+        };
+        if let Some(field) = self
+            .data
+            .nodes
+            .iter_mut()
+            .find(|n| n.id == *node_id)
+            .and_then(|n| n.desc.fields.get_mut(*field_index))
+        {
+            field.value = Var::String(path.display().to_string());
+            field.reset_raw();
+            self.touch();
+        }
+        self.field_picker = None;
+    }
+
+    fn render_nodes(&mut self, ctx: &Context) {
+        let custom_types = self.data.desc_storage.custom_types();
+        let excluded = self.excluded_nodes();
+        let mut browse = None;
+        let mut changed = false;
+        for node in self.data.nodes.iter_mut() {
             let id = Id::new(format!("{}", node.id));
-            let stroke = if node.desc.end {
+            let dimmed = excluded.contains(&node.id);
+            let step = self.execution_order.iter().position(|id| *id == node.id);
+            let stroke = if dimmed {
+                Stroke::new(1.0, Color32::from_gray(60))
+            } else if step.is_some() {
+                Stroke::new(2.0, STEP_COLOR)
+            } else if node.desc.end {
                 Stroke::new(1.0, Color32::from_hex("#C0C000").unwrap())
             } else {
                 Stroke::new(1.0, Color32::from_gray(100))
@@ -172,6 +756,9 @@ impl Workspace {
                 .show(ctx, |ui| {
                     ui.set_min_height(node.size.1);
                     ui.set_min_width(node.size.0);
+                    if dimmed {
+                        ui.multiply_opacity(0.4);
+                    }
                     if node.stabilize_frames < 10 {
                         node.stabilize_frames += 1;
                     } else {
@@ -179,10 +766,47 @@ impl Workspace {
                         node.pos.1 = ui.min_rect().min.y - 38.0;
                     }
 
-                    for field in &mut node.desc.fields {
-                        Self::field_edit(ui, field);
+                    for (i, field) in node.desc.fields.iter_mut().enumerate() {
+                        let value = field.value.clone();
+                        let edit = ui.scope(|ui| {
+                            Self::field_edit(ui, field, &custom_types, &self.data.variables)
+                        });
+                        changed |= field.value != value;
+                        if edit.inner
+                            && let FieldKind::FilePath { extensions } = &field.kind
+                        {
+                            browse = Some(((node.id, i), extensions.clone()));
+                        }
+                        if let Some(error) = Self::field_error(field, &self.data.variables) {
+                            ui.painter().rect_stroke(
+                                edit.response.rect.expand(1.0),
+                                0.0,
+                                Stroke::new(1.0, Color32::LIGHT_RED),
+                                StrokeKind::Outside,
+                            );
+                            edit.response.on_hover_text(error);
+                        }
                     }
                 });
+            if let Some(step) = step {
+                let painter = ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("steps")));
+                let pos = Pos2::new(node.pos.0, node.pos.1);
+                painter.circle_filled(pos, 10.0, STEP_COLOR);
+                painter.text(
+                    pos,
+                    Align2::CENTER_CENTER,
+                    (step + 1).to_string(),
+                    FontId::proportional(12.0),
+                    Color32::BLACK,
+                );
+            }
+        }
+        if changed {
+            self.touch();
+        }
+        if let Some((field, extensions)) = browse {
+            let picker = FilePicker::new(DialogPurpose::PickFile).with_extensions(&extensions);
+            self.field_picker = Some((field, picker));
         }
     }
 
@@ -206,9 +830,165 @@ impl Workspace {
         }
     }
 
-    fn field_edit(ui: &mut Ui, field: &mut FieldDesc) {
-        match field.kind {
+    /// Draws the widget for the field's kind. Returns true if a file browser
+    /// was requested for a `FilePath` field.
+    fn field_edit(
+        ui: &mut Ui,
+        field: &mut FieldDesc,
+        custom_types: &[CustomType],
+        variables: &[Variable],
+    ) -> bool {
+        match field.kind.clone() {
+            FieldKind::Variable => {
+                let Var::String(value) = &mut field.value else {
+                    Self::invalid_value(ui, field);
+                    return false;
+                };
+                let selected = match variable::find(variables, value) {
+                    Some(variable) => format!("{}: {}", variable.name, variable.data_type()),
+                    None if value.is_empty() => "(none)".to_string(),
+                    None => format!("{} (missing)", value),
+                };
+                ComboBox::from_label(&field.name)
+                    .selected_text(selected)
+                    .show_ui(ui, |ui| {
+                        for variable in variables {
+                            let label = format!("{}: {}", variable.name, variable.data_type());
+                            ui.selectable_value(value, variable.name.clone(), label);
+                        }
+                    });
+                field.reset_raw();
+            }
+            FieldKind::Slider { min, max, step } => {
+                match &mut field.value {
+                    Var::Int(v) => {
+                        let mut slider = Slider::new(v, min as i64..=max as i64).text(&field.name);
+                        if let Some(step) = step {
+                            slider = slider.step_by(step);
+                        }
+                        ui.add(slider);
+                    }
+                    Var::Float(v) => {
+                        let mut slider = Slider::new(v, min..=max).text(&field.name);
+                        if let Some(step) = step {
+                            slider = slider.step_by(step);
+                        }
+                        ui.add(slider);
+                    }
+                    _ => Self::invalid_value(ui, field),
+                }
+                field.reset_raw();
+            }
+            FieldKind::Drag { speed, min, max } => {
+                let range = min.unwrap_or(f64::NEG_INFINITY)..=max.unwrap_or(f64::INFINITY);
+                let speed = speed.unwrap_or(1.0);
+                ui.horizontal(|ui| {
+                    ui.label(&field.name);
+                    match &mut field.value {
+                        Var::Int(v) => {
+                            ui.add(DragValue::new(v).speed(speed).range(range));
+                        }
+                        Var::Float(v) => {
+                            ui.add(DragValue::new(v).speed(speed).range(range));
+                        }
+                        _ => {}
+                    }
+                });
+                field.reset_raw();
+            }
+            FieldKind::Dropdown(choices) => {
+                let Var::String(value) = &mut field.value else {
+                    Self::invalid_value(ui, field);
+                    return false;
+                };
+                ComboBox::from_label(&field.name)
+                    .selected_text(value.as_str())
+                    .show_ui(ui, |ui| {
+                        for choice in &choices {
+                            ui.selectable_value(value, choice.clone(), choice);
+                        }
+                    });
+                field.reset_raw();
+            }
+            FieldKind::MultiLine => {
+                let Var::String(value) = &mut field.value else {
+                    Self::invalid_value(ui, field);
+                    return false;
+                };
+                ui.label(&field.name);
+                ui.add(TextEdit::multiline(value).desired_rows(3));
+                field.reset_raw();
+            }
+            FieldKind::Code(language) => {
+                let Var::String(value) = &mut field.value else {
+                    Self::invalid_value(ui, field);
+                    return false;
+                };
+                ui.label(&field.name);
+                CodeEditor::default()
+                    .id_source(format!("{}_code", field.name))
+                    .with_rows(6)
+                    .with_fontsize(12.0)
+                    .with_theme(ColorTheme::GRUVBOX)
+                    .with_syntax(code_syntax(&language))
+                    .with_numlines(true)
+                    .show(ui, value);
+                field.reset_raw();
+            }
+            FieldKind::Color => {
+                let Var::String(value) = &mut field.value else {
+                    Self::invalid_value(ui, field);
+                    return false;
+                };
+                let mut color = Color32::from_hex(value).unwrap_or(Color32::WHITE);
+                ui.horizontal(|ui| {
+                    if ui.color_edit_button_srgba(&mut color).changed() {
+                        *value = color.to_hex();
+                    }
+                    ui.label(&field.name);
+                });
+                field.reset_raw();
+            }
+            FieldKind::FilePath { .. } => {
+                let Var::String(value) = &mut field.value else {
+                    Self::invalid_value(ui, field);
+                    return false;
+                };
+                let mut browse = false;
+                ui.horizontal(|ui| {
+                    ui.add(
+                        TextEdit::singleline(value)
+                            .hint_text(&field.name)
+                            .desired_width(120.0),
+                    );
+                    browse = ui.button("…").on_hover_text("Browse").clicked();
+                });
+                field.reset_raw();
+                return browse;
+            }
             FieldKind::Enter => {
+                if matches!(
+                    field.data_type,
+                    Type::Custom(_) | Type::List(_) | Type::Map(_, _) | Type::Optional(_)
+                ) {
+                    if !custom::matches_shape(&field.value, &field.data_type, custom_types) {
+                        match custom::default_var(&field.data_type, custom_types) {
+                            Some(value) => field.value = value,
+                            None => {
+                                ui.colored_label(
+                                    Color32::LIGHT_RED,
+                                    format!("{}: unknown type {}", field.name, field.data_type),
+                                );
+                                return false;
+                            }
+                        }
+                    }
+                    ui.label(&field.name);
+                    Self::var_edit(ui, &field.name, &mut field.value, custom_types);
+                    field.reset_raw();
+                    return false;
+                }
+
                 if matches!(field.data_type, Type::Bool) {
                     if ui
                         .checkbox(&mut field.value.clone().try_into().unwrap(), "")
@@ -218,7 +998,7 @@ impl Workspace {
                         let current_value: bool = field.value.clone().try_into().unwrap();
                         field.value = (!current_value).into();
                     }
-                    return;
+                    return false;
                 }
 
                 let response = ui.add(
@@ -226,64 +1006,325 @@ impl Workspace {
                         .hint_text(format!("{}: {}", field.name, field.data_type)),
                 );
                 if response.lost_focus() {
-                    if field.raw_value.is_empty() {
-                        field.value = Var::Int(0);
-                        return;
-                    }
+                    field.parse_raw();
+                }
+            }
+        }
+        false
+    }
 
-                    let multi = match &field.data_type {
-                        Type::Multi(m) => Some(m),
-                        _ => None,
-                    };
+    /// Like [`FieldDesc::error`], but also checks that `Variable` fields name
+    /// an existing workspace variable.
+    fn field_error(field: &FieldDesc, variables: &[Variable]) -> Option<String> {
+        if let FieldKind::Variable = field.kind
+            && let Var::String(name) = &field.value
+            && variable::find(variables, name).is_none()
+        {
+            return Some(if name.is_empty() {
+                "No variable selected".to_string()
+            } else {
+                format!("Variable '{}' is not defined", name)
+            });
+        }
+        field.error()
+    }
 
-                    #[allow(clippy::collapsible_if)]
-                    if matches!(field.data_type, Type::Float)
-                        || multi.is_some_and(|m| m.contains(&Type::Float))
-                    {
-                        if let Ok(v) = field.raw_value.parse::<f64>() {
-                            field.value = Var::Float(v);
-                            field.raw_value = v.to_string();
-                            return;
-                        }
-                    };
+    fn invalid_value(ui: &mut Ui, field: &FieldDesc) {
+        ui.colored_label(
+            Color32::LIGHT_RED,
+            format!(
+                "{}: {} is not valid for {:?}",
+                field.name, field.value, field.kind
+            ),
+        );
+    }
 
-                    #[allow(clippy::collapsible_if)]
-                    if matches!(field.data_type, Type::Int)
-                        || multi.is_some_and(|m| m.contains(&Type::Int))
-                    {
-                        if let Ok(v) = field.raw_value.parse::<i64>() {
-                            field.value = Var::Int(v);
-                            field.raw_value = v.to_string();
-                            return;
+    /// Edits a value in place, recursing into the fields of custom values.
+    fn var_edit(ui: &mut Ui, id: &str, value: &mut Var, custom_types: &[CustomType]) {
+        match value {
+            Var::Bool(b) => {
+                ui.checkbox(b, "");
+            }
+            Var::Int(i) => {
+                ui.add(DragValue::new(i));
+            }
+            Var::Float(f) => {
+                ui.add(DragValue::new(f).speed(0.1));
+            }
+            Var::String(s) => {
+                ui.text_edit_singleline(s);
+            }
+            Var::Custom(c) => {
+                Grid::new(format!("custom_{}_{}", id, c.name)).show(ui, |ui| {
+                    for (name, value) in &mut c.fields {
+                        ui.label(name.as_str());
+                        Self::var_edit(ui, &format!("{id}.{name}"), value, custom_types);
+                        ui.end_row();
+                    }
+                });
+            }
+            Var::List(elem, items) => {
+                let mut remove = None;
+                Grid::new(format!("list_{}", id)).show(ui, |ui| {
+                    for (i, item) in items.iter_mut().enumerate() {
+                        ui.label(i.to_string());
+                        Self::var_edit(ui, &format!("{id}[{i}]"), item, custom_types);
+                        if ui.small_button("✕").clicked() {
+                            remove = Some(i);
                         }
-                    };
-
-                    #[allow(clippy::collapsible_if)]
-                    if multi.is_some_and(|m| m.contains(&Type::Bool)) {
-                        if let Ok(v) = field.raw_value.parse::<bool>() {
-                            field.value = Var::Bool(v);
-                            field.raw_value = v.to_string();
-                            return;
+                        ui.end_row();
+                    }
+                });
+                if let Some(i) = remove {
+                    items.remove(i);
+                }
+                if ui.small_button("+").clicked()
+                    && let Some(item) = custom::default_var(elem, custom_types)
+                {
+                    items.push(item);
+                }
+            }
+            Var::Map(key_type, value_type, entries) => {
+                let mut remove = None;
+                // Keys edited to match another entry's, only one would survive
+                let duplicates: Vec<bool> = entries
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (key, _))| {
+                        entries
+                            .iter()
+                            .enumerate()
+                            .any(|(j, (k, _))| i != j && k == key)
+                    })
+                    .collect();
+                Grid::new(format!("map_{}", id)).show(ui, |ui| {
+                    for (i, (key, value)) in entries.iter_mut().enumerate() {
+                        let edit = ui.scope(|ui| {
+                            Self::var_edit(ui, &format!("{id}.k{i}"), key, custom_types)
+                        });
+                        if duplicates[i] {
+                            ui.painter().rect_stroke(
+                                edit.response.rect.expand(1.0),
+                                0.0,
+                                Stroke::new(1.0, Color32::LIGHT_RED),
+                                StrokeKind::Outside,
+                            );
+                            edit.response
+                                .on_hover_text("This key is used more than once");
                         }
-                    };
-
-                    if matches!(field.data_type, Type::String)
-                        || multi.is_some_and(|m| m.contains(&Type::String))
-                    {
-                        field.value = Var::String(field.raw_value.clone());
-                        return;
-                    };
+                        Self::var_edit(ui, &format!("{id}.v{i}"), value, custom_types);
+                        if ui.small_button("✕").clicked() {
+                            remove = Some(i);
+                        }
+                        ui.end_row();
+                    }
+                });
+                if let Some(i) = remove {
+                    entries.remove(i);
+                }
+                // A second entry with the default key would replace the first
+                let key = custom::default_var(key_type, custom_types);
+                let free = key
+                    .as_ref()
+                    .is_some_and(|key| !entries.iter().any(|(k, _)| k == key));
+                if ui
+                    .add_enabled(free, Button::new("+").small())
+                    .on_disabled_hover_text("Change the key of the new entry first")
+                    .clicked()
+                    && let Some(key) = key
+                    && let Some(value) = custom::default_var(value_type, custom_types)
+                {
+                    entries.push((key, value));
+                }
+            }
+            Var::Optional(inner, value) => {
+                ui.horizontal(|ui| {
+                    let mut is_some = value.is_some();
+                    if ui.checkbox(&mut is_some, "").changed() {
+                        *value = if is_some {
+                            custom::default_var(inner, custom_types).map(Box::new)
+                        } else {
+                            None
+                        };
+                    }
+                    if let Some(value) = value {
+                        Self::var_edit(ui, &format!("{id}?"), value, custom_types);
+                    }
+                });
+            }
+        }
+    }
 
-                    println!(
-                        "Failed to parse value for field '{}' ('{}': {})",
-                        field.name, field.raw_value, field.data_type
-                    );
+    /// Whether a connection from the output `from` to the input `to` is type compatible.
+    pub fn connection_compatible(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        let Some(output_type) = self.output_type(from.0, from.1) else {
+            return true;
+        };
+        let Some(node) = self.data.nodes.iter().find(|n| n.id == to.0) else {
+            return false;
+        };
+        let Some(input) = node.desc.inputs.get(to.1) else {
+            return false;
+        };
+        let custom_types = self.data.desc_storage.custom_types();
+        let input_type = match &input.data_type {
+            Type::Inherit(inherited) if inherited.starts_with("va_") => {
+                match self.variable_type(to.0, &inherited[3..]) {
+                    Some(data_type) => data_type,
+                    None => return true,
                 }
             }
+            data_type => data_type.clone(),
+        };
+        if !custom::compatible(&input_type, &output_type, &custom_types) {
+            return Coercion::find(&output_type, &input_type).is_some();
+        }
+        // Inputs other ports take the element type of (`el_`) must be lists
+        let element_of = Type::Inherit(format!("el_{}", input.name));
+        let maybe_list =
+            |t: &Type| matches!(t, Type::List(_) | Type::Inherit(_) | Type::Generic(_));
+        if node.desc.outputs.iter().any(|o| o.data_type == element_of)
+            && !maybe_list(&output_type)
+            && !matches!(&output_type, Type::Multi(types) if types.iter().any(maybe_list))
+        {
+            return false;
+        }
+        if !generics::contains_generic(&input.data_type) {
+            return true;
+        }
+        // The new connection replaces whatever is connected to `to`, and must
+        // agree with the type variables bound by the other inputs.
+        let mut input_types = self.connected_input_types(to.0, Some(to.1), 0);
+        input_types.push((to.1, output_type));
+        generics::bind(&node.desc, input_types, &custom_types).is_ok()
+    }
+
+    /// Type of the workspace variable named by the field `field_name` of a node.
+    fn variable_type(&self, node_id: usize, field_name: &str) -> Option<Type> {
+        let node = self.data.nodes.iter().find(|n| n.id == node_id)?;
+        let field = node.desc.fields.iter().find(|f| f.name == field_name)?;
+        let Var::String(name) = &field.value else {
+            return None;
+        };
+        variable::find(&self.data.variables, name).map(|v| v.data_type())
+    }
+
+    /// The conversion the compiler inserts for the connection from `from` to `to`, if any.
+    pub fn connection_coercion(
+        &self,
+        from: (usize, usize),
+        to: (usize, usize),
+    ) -> Option<Coercion> {
+        let output_type = self.output_type(from.0, from.1)?;
+        let node = self.data.nodes.iter().find(|n| n.id == to.0)?;
+        let input = node.desc.inputs.get(to.1)?;
+        let input_type = if generics::contains_generic(&input.data_type) {
+            let input_types = self.connected_input_types(to.0, None, 0);
+            let bindings = generics::bind(
+                &node.desc,
+                input_types,
+                &self.data.desc_storage.custom_types(),
+            )
+            .ok()?;
+            generics::resolve(&input.data_type, &node.desc.generics, &bindings)
+        } else {
+            input.data_type.clone()
+        };
+        if custom::compatible(
+            &input_type,
+            &output_type,
+            &self.data.desc_storage.custom_types(),
+        ) {
+            return None;
+        }
+        Coercion::find(&output_type, &input_type)
+    }
+
+    /// Resolved types of the outputs connected to the inputs of `node_id`, as
+    /// `(input index, type)`, leaving out the input `except`.
+    fn connected_input_types(
+        &self,
+        node_id: usize,
+        except: Option<usize>,
+        depth: usize,
+    ) -> Vec<(usize, Type)> {
+        self.data
+            .connections
+            .iter()
+            .filter(|c| c.to.0 == node_id && Some(c.to.1) != except)
+            .filter_map(|c| {
+                let data_type = self.output_type_recursive(c.from.0, c.from.1, depth + 1)?;
+                Some((c.to.1, data_type))
+            })
+            .collect()
+    }
+
+    /// Resolves the type of an output port, following `Inherit` references to
+    /// fields (`fi_`), to whatever is connected to an input (`ti_`) or its
+    /// elements (`el_`), and
+    /// binding type variables from the connected inputs.
+    pub fn output_type(&self, node_id: usize, port: usize) -> Option<Type> {
+        self.output_type_recursive(node_id, port, 0)
+    }
+
+    fn output_type_recursive(&self, node_id: usize, port: usize, depth: usize) -> Option<Type> {
+        let node = self.data.nodes.iter().find(|n| n.id == node_id)?;
+        let data_type = node.desc.outputs.get(port)?.data_type.clone();
+        if depth > self.data.nodes.len() {
+            return None;
+        }
+        let Type::Inherit(inherited) = &data_type else {
+            if !generics::contains_generic(&data_type) {
+                return Some(data_type);
+            }
+            let input_types = self.connected_input_types(node_id, None, depth);
+            let bindings = generics::bind(
+                &node.desc,
+                input_types,
+                &self.data.desc_storage.custom_types(),
+            )
+            .unwrap_or_default();
+            return Some(generics::resolve(
+                &data_type,
+                &node.desc.generics,
+                &bindings,
+            ));
+        };
+
+        if let Some(field_name) = inherited.strip_prefix("fi_") {
+            let field = node.desc.fields.iter().find(|f| f.name == field_name)?;
+            Some(field.value.type_())
+        } else if let Some(field_name) = inherited.strip_prefix("va_") {
+            self.variable_type(node_id, field_name)
+        } else if let Some(input_name) = inherited.strip_prefix("ti_") {
+            let input_index = node.desc.inputs.iter().position(|i| i.name == input_name)?;
+            match self
+                .data
+                .connections
+                .iter()
+                .find(|c| c.to == (node_id, input_index))
+            {
+                Some(conn) => self.output_type_recursive(conn.from.0, conn.from.1, depth + 1),
+                None => Some(node.desc.inputs[input_index].data_type.clone()),
+            }
+        } else if let Some(input_name) = inherited.strip_prefix("el_") {
+            let input_index = node.desc.inputs.iter().position(|i| i.name == input_name)?;
+            let conn = self
+                .data
+                .connections
+                .iter()
+                .find(|c| c.to == (node_id, input_index))?;
+            match self.output_type_recursive(conn.from.0, conn.from.1, depth + 1)? {
+                Type::List(element) => Some(*element),
+                _ => None,
+            }
+        } else {
+            None
         }
     }
 
     pub fn verify_connections(&mut self) {
+        self.touch();
         self.data
             .connections
             .retain(|conn| conn.from.0 != conn.to.0);
@@ -323,21 +1364,343 @@ impl Workspace {
         None
     }
 
-    pub fn add_node(&mut self, category: String, title: String) {
-        if let Some(desc) = self.data.desc_storage.desc(&category, &title) {
-            self.data.nodes.push(Node {
-                id: self.data.nodes.len(),
-                pos: self.shared.borrow().add_menu.clone().unwrap().0.into(),
-                size: (
-                    120.0,
-                    ((desc.inputs.len() + desc.outputs.len()) as f32) * 20.0,
-                ),
-                desc: desc.clone(),
-                stabilize_frames: 0,
-            });
-            self.shared.borrow_mut().add_menu = None;
+    /// Every field whose value is invalid, as `(node title#id, field name, error)`.
+    pub fn invalid_fields(&self) -> Vec<(String, String, String)> {
+        self.data
+            .nodes
+            .iter()
+            .flat_map(|node| {
+                node.desc.fields.iter().filter_map(|field| {
+                    let error = Self::field_error(field, &self.data.variables)?;
+                    let node = format!("{}#{}", node.desc.title, node.id);
+                    Some((node, field.name.clone(), error))
+                })
+            })
+            .collect()
+    }
+
+    pub fn add_node(&mut self, category: String, title: String, pos: Pos2) -> Option<usize> {
+        let id = self.data.nodes.len();
+        if let Some(mut node) = Node::new(&self.data.desc_storage, id, &category, &title) {
+            node.pos = pos.into();
+            self.touch();
+            self.data.nodes.push(node);
+            Some(id)
         } else {
             error!("Node description not found for {}:{}", category, title);
+            None
+        }
+    }
+}
+
+fn report_failed_libs(shared: &Rc<RefCell<Shared>>, failed: &[(PathBuf, anyhow::Error)]) {
+    if failed.is_empty() {
+        return;
+    }
+    let list = failed
+        .iter()
+        .map(|(path, e)| format!("{}: {e:?}", path.display()))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    error!("Failed to import libs:\n{list}");
+    shared.borrow_mut().error = Some(format!("Failed to import libs:\n{list}"));
+}
+
+/// Shows what the loaded libraries changed in a workspace, since saving it
+/// would make dropped values and connections permanent.
+fn report_load_changes(shared: &Rc<RefCell<Shared>>, changes: &[String]) {
+    if changes.is_empty() {
+        return;
+    }
+    let list = changes.join("\n");
+    warn!("Loaded the workspace with changes:\n{list}");
+    let message = format!("The loaded libraries changed the workspace:\n{list}");
+    let mut shared = shared.borrow_mut();
+    shared.error = Some(match shared.error.take() {
+        Some(error) => format!("{error}\n\n{message}"),
+        None => message,
+    });
+}
+
+/// Highlighting for a `Code` field's language; unknown languages are plain text.
+fn code_syntax(language: &str) -> Syntax {
+    match language.to_lowercase().as_str() {
+        "python" | "python3" => Syntax::python(),
+        "rust" => Syntax::rust(),
+        "lua" => Syntax::lua(),
+        "sql" => Syntax::sql(),
+        "shell" | "sh" | "bash" => Syntax::shell(),
+        "asm" => Syntax::asm(),
+        _ => Syntax::new("text")
+            .with_keywords([])
+            .with_types([])
+            .with_special([]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use eframe::egui::Pos2;
+    use std::{cell::RefCell, path::Path, rc::Rc};
+
+    use super::{Workspace, WorkspaceFormat};
+    use crate::{
+        graph::{CustomValue, Type, Var, Variable},
+        Shared,
+    };
+
+    fn shared() -> Rc<RefCell<Shared>> {
+        Rc::new(RefCell::new(Shared {
+            cursor: Pos2::ZERO,
+            error: None,
+            compile_debug_info: false,
+            compile_optimize: false,
+            library_dirs: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn test_load_baseline() {
+        // Saved before nodes referred to their descriptors
+        let text = include_str!("../testdata/baseline.no3");
+        let workspace = Workspace::from_text(
+            shared(),
+            text,
+            WorkspaceFormat::Ron,
+            Path::new("baseline.no3"),
+            Vec::new(),
+        )
+        .unwrap();
+        let nodes = &workspace.data.nodes;
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(nodes[2].desc_ref.category, "Math");
+        assert_eq!(nodes[2].desc_ref.title, "Add");
+        assert!(matches!(nodes[0].desc.fields[0].value, Var::Float(v) if v == 1.5));
+        assert_eq!(workspace.data.connections.len(), 2);
+    }
+
+    #[test]
+    fn test_json_non_finite() {
+        let mut workspace = Workspace::new(shared());
+        workspace.import_std_libs().unwrap();
+        let id = workspace
+            .add_node("Math".to_string(), "Const".to_string(), Pos2::ZERO)
+            .unwrap();
+        workspace.data.nodes[id].desc.fields[0].value = Var::Float(f64::INFINITY);
+        let error = workspace
+            .to_text(WorkspaceFormat::Json, Path::new("."))
+            .unwrap_err();
+        assert!(error.to_string().contains("Const#0 field 'value'"));
+        assert!(workspace
+            .to_text(WorkspaceFormat::Yaml, Path::new("."))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_rename_variable() {
+        let mut workspace = Workspace::new(shared());
+        workspace.import_std_libs().unwrap();
+        workspace.data.variables.push(Variable {
+            name: "a".to_string(),
+            value: Var::Int(0),
+            parameter: false,
+        });
+        let id = workspace
+            .add_node("Variables".to_string(), "Get".to_string(), Pos2::ZERO)
+            .unwrap();
+        workspace.data.nodes[id].desc.fields[0].value = Var::String("a".to_string());
+
+        workspace.rename_variable(0, String::new());
+        workspace.rename_variable(0, "1a".to_string());
+        assert_eq!(workspace.data.variables[0].name, "a");
+        workspace.rename_variable(0, "b".to_string());
+        assert_eq!(workspace.data.variables[0].name, "b");
+        assert_eq!(
+            workspace.data.nodes[id].desc.fields[0].value,
+            Var::String("b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut workspace = Workspace::new(shared());
+        workspace.import_std_libs().unwrap();
+        let vec2 = workspace
+            .add_node(
+                "Math".to_string(),
+                "Vec2".to_string(),
+                Pos2::new(10.0, 20.0),
+            )
+            .unwrap();
+        let custom = Var::Custom(CustomValue {
+            name: "Vec2".to_string(),
+            fields: vec![
+                ("x".to_string(), Var::Float(1.5)),
+                ("y".to_string(), Var::Float(-2.0)),
+            ],
+        });
+        workspace.data.nodes[vec2].desc.fields[0].value = custom;
+        let get = workspace
+            .add_node("Variables".to_string(), "Get".to_string(), Pos2::ZERO)
+            .unwrap();
+        workspace.data.nodes[get].desc.fields[0].value = Var::String("names".to_string());
+        let variable = |name: &str, value| Variable {
+            name: name.to_string(),
+            value,
+            parameter: false,
+        };
+        workspace.data.variables = vec![
+            variable(
+                "names",
+                Var::List(
+                    Type::String,
+                    vec![Var::String("a".to_string()), Var::String("b".to_string())],
+                ),
+            ),
+            variable(
+                "scores",
+                Var::Map(
+                    Type::String,
+                    Type::Int,
+                    vec![(Var::String("a".to_string()), Var::Int(3))],
+                ),
+            ),
+            variable(
+                "limit",
+                Var::Optional(Type::Float, Some(Box::new(Var::Float(0.25)))),
+            ),
+            variable("none", Var::Optional(Type::Int, None)),
+        ];
+        workspace.data.variables[2].parameter = true;
+
+        for (format, file) in [
+            (WorkspaceFormat::Yaml, "test.no3.yaml"),
+            (WorkspaceFormat::Json, "test.no3.json"),
+        ] {
+            let text = workspace.to_text(format, Path::new(".")).unwrap();
+            if format == WorkspaceFormat::Json {
+                let schema: serde_json::Value =
+                    serde_json::from_str(include_str!("../docs/workspace.schema.json")).unwrap();
+                let document: serde_json::Value = serde_json::from_str(&text).unwrap();
+                if let Err(e) = schema::check(&schema, &schema, &document, "") {
+                    panic!("Saved JSON doesn't match the schema: {e}");
+                }
+                let mut broken = document.clone();
+                broken["nodes"][0]["pos"] = serde_json::json!([1.0]);
+                assert!(schema::check(&schema, &schema, &broken, "").is_err());
+            }
+            let shared = shared();
+            let loaded =
+                Workspace::from_text(shared.clone(), &text, format, Path::new(file), Vec::new())
+                    .unwrap();
+            assert!(shared.borrow().error.is_none());
+            for (saved, loaded) in workspace.data.nodes.iter().zip(&loaded.data.nodes) {
+                assert_eq!(saved.desc_ref, loaded.desc_ref);
+                assert_eq!(saved.pos, loaded.pos);
+                assert_eq!(saved.desc.fields[0].value, loaded.desc.fields[0].value);
+            }
+            assert_eq!(loaded.data.nodes.len(), 2);
+            assert_eq!(loaded.data.variables.len(), 4);
+            for (saved, loaded) in workspace.data.variables.iter().zip(&loaded.data.variables) {
+                assert_eq!(saved.name, loaded.name);
+                assert_eq!(saved.value, loaded.value);
+                assert_eq!(saved.parameter, loaded.parameter);
+            }
+        }
+    }
+
+    /// Checks a value against the parts of JSON Schema that
+    /// docs/workspace.schema.json uses.
+    mod schema {
+        use serde_json::Value;
+
+        pub fn check(root: &Value, schema: &Value, value: &Value, at: &str) -> Result<(), String> {
+            let Some(schema) = schema.as_object() else {
+                return match schema {
+                    Value::Bool(false) => Err(format!("{at}: not allowed")),
+                    _ => Ok(()),
+                };
+            };
+            if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+                let name = reference.trim_start_matches("#/$defs/");
+                return check(root, &root["$defs"][name], value, at);
+            }
+            if let Some(options) = schema.get("oneOf").and_then(Value::as_array) {
+                let matching = options
+                    .iter()
+                    .filter(|option| check(root, option, value, at).is_ok())
+                    .count();
+                if matching != 1 {
+                    return Err(format!("{at}: matches {matching} of oneOf: {value}"));
+                }
+            }
+            if let Some(allowed) = schema.get("enum").and_then(Value::as_array)
+                && !allowed.contains(value)
+            {
+                return Err(format!("{at}: {value} is not one of {allowed:?}"));
+            }
+            if let Some(expected) = schema.get("type").and_then(Value::as_str) {
+                let matches = match expected {
+                    "object" => value.is_object(),
+                    "array" => value.is_array(),
+                    "string" => value.is_string(),
+                    "integer" => value.is_i64() || value.is_u64(),
+                    "number" => value.is_number(),
+                    "boolean" => value.is_boolean(),
+                    "null" => value.is_null(),
+                    _ => false,
+                };
+                if !matches {
+                    return Err(format!("{at}: {value} is not of type {expected}"));
+                }
+            }
+            if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64)
+                && value.as_f64().is_some_and(|v| v < minimum)
+            {
+                return Err(format!("{at}: {value} is less than {minimum}"));
+            }
+            if let Some(object) = value.as_object() {
+                let properties = schema.get("properties").and_then(Value::as_object);
+                let required = schema.get("required").and_then(Value::as_array);
+                for required in required.into_iter().flatten() {
+                    let name = required.as_str().unwrap_or_default();
+                    if !object.contains_key(name) {
+                        return Err(format!("{at}: missing {name}"));
+                    }
+                }
+                for (name, item) in object {
+                    let at = format!("{at}.{name}");
+                    let property = properties
+                        .and_then(|p| p.get(name))
+                        .or_else(|| schema.get("additionalProperties"));
+                    if let Some(property) = property {
+                        check(root, property, item, &at)?;
+                    }
+                }
+            }
+            if let Some(array) = value.as_array() {
+                let prefix = schema.get("prefixItems").and_then(Value::as_array);
+                let prefix_len = prefix.map_or(0, Vec::len);
+                for (i, item) in array.iter().enumerate() {
+                    let at = format!("{at}[{i}]");
+                    match prefix.and_then(|p| p.get(i)) {
+                        Some(item_schema) => check(root, item_schema, item, &at)?,
+                        None if i >= prefix_len => {
+                            if let Some(items) = schema.get("items") {
+                                check(root, items, item, &at)?;
+                            }
+                        }
+                        None => {}
+                    }
+                }
+                let len = array.len() as u64;
+                let min = schema.get("minItems").and_then(Value::as_u64);
+                let max = schema.get("maxItems").and_then(Value::as_u64);
+                if min.is_some_and(|m| len < m) || max.is_some_and(|m| len > m) {
+                    return Err(format!("{at}: wrong number of items"));
+                }
+            }
+            Ok(())
         }
     }
 }